@@ -0,0 +1,247 @@
+use bevy::prelude::*;
+
+use crate::{
+    level::{Level, Tile},
+    platform::Maps,
+    GRAPPLE_SPEED, GRAVITY_CONSTANT, MAP_SCALE, PLAYER_JUMP_VELOCITY, PLAYER_RUN_SPEED,
+};
+
+// moves can be this much faster than the physics allows, for frame times and rounding
+const SPEED_TOLERANCE: f32 = 1.5;
+// every move can go this much further, for packets that arrive in a burst
+// after lag or being carried by a wall someone else is moving
+const DISTANCE_SLACK: f32 = MAP_SCALE;
+// how close to a spawn or goal a player has to be to use it
+const TILE_REACH: f32 = MAP_SCALE * 1.5;
+// a client that makes this many bad moves is kicked
+const MAX_VIOLATIONS: u32 = 20;
+// one bad move is forgotten every this many seconds so a laggy
+// connection doesn't add up to a kick over a long game
+const VIOLATION_DECAY: f64 = 5.0;
+
+// what the server does with a position a client sent
+pub enum Verdict {
+    // send it on to everyone else
+    Accept,
+    // drop it without counting it, eg it is from the next level and
+    // the goal message hasn't arrived yet
+    Ignore,
+    // it can't have happened, the reason is printed
+    Reject(String),
+}
+
+// the server's idea of where a client can be. positions are checked against
+// the same maps the server sends out
+pub struct MovementCheck {
+    // the level the client is on, it only goes up by reaching the goal
+    level: u8,
+    // the last position that was accepted and when (the server's clock).
+    // None until the client is seen at the spawn of the level
+    last: Option<(Vec2, f64)>,
+    violations: u32,
+    // when a violation was last forgotten
+    last_decay: f64,
+}
+
+impl Default for MovementCheck {
+    fn default() -> Self {
+        MovementCheck {
+            level: 1,
+            last: None,
+            violations: 0,
+            last_decay: 0.0,
+        }
+    }
+}
+
+impl MovementCheck {
+    pub fn check_position(&mut self, maps: &Maps, level: u8, position: Vec2, now: f64) -> Verdict {
+        // positions are unreliable and the goal is reliable so they can arrive
+        // after the goal or overtake it
+        if level.abs_diff(self.level) == 1 {
+            return Verdict::Ignore;
+        }
+        if level != self.level {
+            return Verdict::Reject(format!(
+                "said it is on level {level} but it is on level {}",
+                self.level
+            ));
+        }
+        let Some(map) = maps.maps.get(&level) else {
+            return Verdict::Reject(format!("said it is on level {level} which doesn't exist"));
+        };
+
+        // respawning puts the player back at the spawn from anywhere
+        if near_tile(map, Tile::Spawn, position, TILE_REACH) {
+            self.last = Some((position, now));
+            return Verdict::Accept;
+        }
+
+        let Some((last, time)) = self.last else {
+            return Verdict::Reject(format!("started level {level} away from the spawn"));
+        };
+        let distance = position.distance(last);
+        let reach = reach(map, now - time);
+        if distance > reach {
+            return Verdict::Reject(format!(
+                "moved {distance:.0} in {:.2}s, the most it could move is {reach:.0}",
+                now - time
+            ));
+        }
+
+        self.last = Some((position, now));
+        Verdict::Accept
+    }
+
+    // the client says it touched the goal. it has to have been close enough
+    // to one since its last position
+    pub fn reach_goal(&mut self, maps: &Maps, level: u8, now: f64) -> Result<(), String> {
+        if level != self.level {
+            return Err(format!(
+                "finished level {level} but it is on level {}",
+                self.level
+            ));
+        }
+        let Some(map) = maps.maps.get(&level) else {
+            return Err(format!("finished level {level} which doesn't exist"));
+        };
+        let Some((last, time)) = self.last else {
+            return Err(format!("finished level {level} without moving"));
+        };
+
+        if !near_tile(map, Tile::Goal, last, TILE_REACH + reach(map, now - time)) {
+            return Err(format!(
+                "finished level {level} from {last} which is nowhere near a goal"
+            ));
+        }
+
+        // the next position should be at the spawn of the next level
+        self.level += 1;
+        self.last = None;
+        Ok(())
+    }
+
+    // counts a bad move, true if the client has made too many and should be kicked
+    pub fn violation(&mut self, now: f64) -> bool {
+        let forgiven = ((now - self.last_decay) / VIOLATION_DECAY) as u32;
+        if forgiven > 0 {
+            self.violations = self.violations.saturating_sub(forgiven);
+            self.last_decay = now;
+        }
+
+        self.violations += 1;
+        self.violations >= MAX_VIOLATIONS
+    }
+}
+
+// how far a player can move on a level in some time
+fn reach(level: &Level, seconds: f64) -> f32 {
+    max_speed(level) * seconds.max(0.0) as f32 * SPEED_TOLERANCE + DISTANCE_SLACK
+}
+
+// the fastest a player can go on a level. gravity is the only thing that adds
+// to the velocity, so it is fastest after jumping from the top of the map and
+// falling to the LowestPoint, then running and reeling in the hook on top
+fn max_speed(level: &Level) -> f32 {
+    let gravity = -GRAVITY_CONSTANT.y;
+    let jump_height = PLAYER_JUMP_VELOCITY.powi(2) / (2.0 * gravity);
+    let height = level.tiles.len() as f32 * MAP_SCALE;
+    let fall = height + height / 2.0 + MAP_SCALE + 100.0 + jump_height;
+
+    (2.0 * gravity * fall).sqrt() + PLAYER_RUN_SPEED + GRAPPLE_SPEED
+}
+
+// whether there is a tile of that kind within distance of the position
+fn near_tile(level: &Level, tile: Tile, position: Vec2, distance: f32) -> bool {
+    level.tiles.iter().enumerate().any(|(y, row)| {
+        row.iter().enumerate().any(|(x, code)| {
+            *code == tile.code()
+                && (Vec2::new(x as f32, y as f32) * MAP_SCALE).distance(position) <= distance
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // one row with the spawn at the left and the goal `goal` tiles along
+    fn maps(goal: usize) -> Maps {
+        let mut row = vec![Tile::Empty.code(); goal + 1];
+        row[0] = Tile::Spawn.code();
+        row[goal] = Tile::Goal.code();
+        let level = Level {
+            name: String::new(),
+            author: String::new(),
+            par_time: None,
+            tiles: vec![row],
+        };
+        Maps {
+            maps: [(1, level)].into_iter().collect(),
+        }
+    }
+
+    fn accepted(verdict: Verdict) -> bool {
+        matches!(verdict, Verdict::Accept)
+    }
+
+    fn rejected(verdict: Verdict) -> bool {
+        matches!(verdict, Verdict::Reject(_))
+    }
+
+    #[test]
+    fn teleport_is_rejected() {
+        let maps = maps(39);
+        let mut check = MovementCheck::default();
+        assert!(accepted(check.check_position(&maps, 1, Vec2::ZERO, 0.0)));
+
+        // straight to the goal at the other end of the level
+        let goal = Vec2::new(39.0 * MAP_SCALE, 0.0);
+        assert!(rejected(check.check_position(&maps, 1, goal, 0.1)));
+        assert!(check.reach_goal(&maps, 1, 0.1).is_err());
+    }
+
+    #[test]
+    fn level_skip_is_rejected() {
+        let maps = maps(39);
+        let mut check = MovementCheck::default();
+        assert!(accepted(check.check_position(&maps, 1, Vec2::ZERO, 0.0)));
+
+        assert!(rejected(check.check_position(&maps, 3, Vec2::ZERO, 0.1)));
+        assert!(check.reach_goal(&maps, 2, 0.1).is_err());
+        assert_eq!(check.level, 1);
+    }
+
+    #[test]
+    fn goal_reached_nearby_is_accepted() {
+        let maps = maps(2);
+        let mut check = MovementCheck::default();
+        assert!(accepted(check.check_position(&maps, 1, Vec2::ZERO, 0.0)));
+        let near_goal = Vec2::new(1.5 * MAP_SCALE, 0.0);
+        assert!(accepted(check.check_position(&maps, 1, near_goal, 0.1)));
+
+        assert!(check.reach_goal(&maps, 1, 0.2).is_ok());
+        assert_eq!(check.level, 2);
+        // a late position from the level just finished isn't counted
+        assert!(matches!(
+            check.check_position(&maps, 1, near_goal, 0.2),
+            Verdict::Ignore
+        ));
+    }
+
+    #[test]
+    fn violations_decay() {
+        let mut check = MovementCheck::default();
+        for _ in 1..MAX_VIOLATIONS {
+            assert!(!check.violation(0.0));
+        }
+
+        // two are forgotten by now, so this one doesn't get them kicked
+        assert!(!check.violation(VIOLATION_DECAY * 2.0));
+        assert_eq!(check.violations, MAX_VIOLATIONS - 2);
+
+        // but it still adds up without the time to forget
+        assert!(!check.violation(VIOLATION_DECAY * 2.0));
+        assert!(check.violation(VIOLATION_DECAY * 2.0));
+    }
+}
@@ -0,0 +1,268 @@
+use std::{
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use bevy_renet::renet::{ConnectToken, ServerAuthentication, NETCODE_KEY_BYTES};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{config_directory, config_path},
+    hosting::lan_ip,
+    messages::{clean_name, name_to_user_data, PROTOCOL_ID},
+    settings::load_settings,
+};
+
+// the host's private key is kept in the config folder as hex so the same
+// tokens keep working after a restart
+const KEY_FILE: &str = "server_key.txt";
+// players put the token files they are given in this folder in their config folder
+const TOKEN_FOLDER: &str = "tokens";
+const TOKEN_EXTENSION: &str = "token";
+// how long an issued token can be used to connect for if no time is given
+const DEFAULT_TOKEN_HOURS: u64 = 24;
+// how long the host's own token lasts, it is made again every time it hosts
+const HOST_TOKEN_LIFETIME: Duration = Duration::from_secs(60 * 60);
+// seconds without packets before a connection times out, the same as renet's unsecure one
+const TOKEN_TIMEOUT_SECONDS: i32 = 15;
+
+// the secret only the server knows. tokens made with it are the only way in
+pub type PrivateKey = [u8; NETCODE_KEY_BYTES];
+
+fn now() -> Duration {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+}
+
+// secure servers check every connect token with the key, anyone can join an unsecure one
+pub fn server_authentication(private_key: Option<PrivateKey>) -> ServerAuthentication {
+    match private_key {
+        Some(private_key) => ServerAuthentication::Secure { private_key },
+        None => ServerAuthentication::Unsecure,
+    }
+}
+
+// reads the host's private key, or makes one the first time it is needed
+pub fn load_or_create_key() -> Result<PrivateKey, String> {
+    let path = config_path(KEY_FILE);
+    if let Ok(contents) = fs::read_to_string(&path) {
+        return decode_key(contents.trim()).ok_or_else(|| {
+            format!(
+                "{} is not a valid key, delete it to make a new one",
+                path.display()
+            )
+        });
+    }
+
+    let key: PrivateKey = rand::random();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("could not create {}: {e}", dir.display()))?;
+    }
+    fs::write(&path, encode_key(&key))
+        .map_err(|e| format!("could not save the key to {}: {e}", path.display()))?;
+    println!("made a new server key in {}", path.display());
+    Ok(key)
+}
+
+fn encode_key(key: &PrivateKey) -> String {
+    key.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn decode_key(hex: &str) -> Option<PrivateKey> {
+    if hex.len() != NETCODE_KEY_BYTES * 2 || !hex.is_ascii() {
+        return None;
+    }
+    let mut key = [0; NETCODE_KEY_BYTES];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(key)
+}
+
+// a connect token for one player on one server. the name is sealed inside it
+// so the server knows who it is and it can't be changed
+pub fn connect_token(
+    private_key: &PrivateKey,
+    server: SocketAddr,
+    client_id: u64,
+    name: &str,
+    valid_for: Duration,
+) -> Result<ConnectToken, String> {
+    ConnectToken::generate(
+        now(),
+        PROTOCOL_ID,
+        valid_for.as_secs(),
+        client_id,
+        TOKEN_TIMEOUT_SECONDS,
+        vec![server],
+        Some(&name_to_user_data(name)),
+        private_key,
+    )
+    .map_err(|e| format!("could not make a token: {e}"))
+}
+
+// the token the host's own client joins its secure server with
+pub fn host_token(
+    private_key: &PrivateKey,
+    server: SocketAddr,
+    client_id: u64,
+    name: &str,
+) -> ConnectToken {
+    connect_token(private_key, server, client_id, name, HOST_TOKEN_LIFETIME).unwrap()
+}
+
+// what is given to a player so they can join a secure server. the server and
+// name are kept next to the token so the join menu can show them
+#[derive(Serialize, Deserialize)]
+pub struct TokenFile {
+    pub server: SocketAddr,
+    pub name: String,
+    // seconds since 1970
+    pub expires: u64,
+    token: Vec<u8>,
+}
+
+impl TokenFile {
+    pub fn issue(
+        private_key: &PrivateKey,
+        server: SocketAddr,
+        name: &str,
+        valid_for: Duration,
+    ) -> Result<Self, String> {
+        let token = connect_token(private_key, server, rand::random(), name, valid_for)?;
+        let mut bytes = vec![];
+        token
+            .write(&mut bytes)
+            .map_err(|e| format!("could not write the token: {e}"))?;
+
+        Ok(TokenFile {
+            server,
+            name: clean_name(name),
+            expires: (now() + valid_for).as_secs(),
+            token: bytes,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let bytes =
+            fs::read(path).map_err(|e| format!("could not read {}: {e}", path.display()))?;
+        bincode::deserialize(&bytes).map_err(|_| format!("{} is not a token", path.display()))
+    }
+
+    fn save(&self, path: &Path) -> Result<(), String> {
+        fs::write(path, bincode::serialize(self).unwrap())
+            .map_err(|e| format!("could not write {}: {e}", path.display()))
+    }
+
+    pub fn expired(&self) -> bool {
+        now().as_secs() >= self.expires
+    }
+
+    pub fn connect_token(&self) -> Result<ConnectToken, String> {
+        ConnectToken::read(&mut self.token.as_slice())
+            .map_err(|e| format!("the token for {} is broken: {e}", self.server))
+    }
+}
+
+// the folder the join menu looks for tokens in
+pub fn token_directory() -> PathBuf {
+    config_directory().join(TOKEN_FOLDER)
+}
+
+// every token file the player has been given, sorted by file name
+pub fn saved_tokens() -> Vec<(PathBuf, TokenFile)> {
+    let Ok(entries) = fs::read_dir(token_directory()) else {
+        return vec![];
+    };
+
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == TOKEN_EXTENSION))
+        .collect();
+    paths.sort();
+
+    paths
+        .into_iter()
+        .filter_map(|path| match TokenFile::load(&path) {
+            Ok(token) => Some((path, token)),
+            Err(e) => {
+                println!("{e}");
+                None
+            }
+        })
+        .collect()
+}
+
+// makes a token file for a player with the host's key.
+// usage: multiplayer_platformer --issue-token <name> [hours] [server address]
+pub fn run_issue_token(args: &[String]) {
+    let mut args = args
+        .iter()
+        .skip_while(|a| a.as_str() != "--issue-token")
+        .skip(1);
+
+    let Some(name) = args
+        .next()
+        .map(|name| clean_name(name))
+        .filter(|name| !name.is_empty())
+    else {
+        println!("usage: multiplayer_platformer --issue-token <name> [hours] [server address]");
+        return;
+    };
+
+    let hours = match args.next().map(|hours| hours.parse::<u64>()) {
+        Some(Ok(hours)) => hours,
+        Some(Err(_)) => {
+            println!("the number of hours the token lasts has to be a whole number");
+            return;
+        }
+        None => DEFAULT_TOKEN_HOURS,
+    };
+    // the time it runs out has to fit in the token
+    let Some(valid_for) = hours
+        .checked_mul(60 * 60)
+        .filter(|seconds| now().as_secs().checked_add(*seconds).is_some())
+    else {
+        println!("the number of hours the token lasts is too big");
+        return;
+    };
+
+    // the token only works for the exact address the server gives out
+    let settings = load_settings();
+    let server = match args.next().map(|address| address.parse::<SocketAddr>()) {
+        Some(Ok(address)) => address,
+        Some(Err(_)) => {
+            println!("the server address has to be ip:port");
+            return;
+        }
+        None => SocketAddr::new(lan_ip(), settings.server_port),
+    };
+
+    let result = load_or_create_key()
+        .and_then(|key| TokenFile::issue(&key, server, &name, Duration::from_secs(valid_for)));
+    let token = match result {
+        Ok(token) => token,
+        Err(e) => {
+            println!("{e}");
+            return;
+        }
+    };
+
+    // keep the file name simple, names can have any characters in them
+    let file_name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let path = PathBuf::from(format!("{file_name}.{TOKEN_EXTENSION}"));
+    match token.save(&path) {
+        Ok(()) => println!(
+            "made {} for {name} to join {server} for {hours} hours. they put it in {}",
+            path.display(),
+            token_directory().display()
+        ),
+        Err(e) => println!("{e}"),
+    }
+}
@@ -0,0 +1,206 @@
+use std::{collections::VecDeque, time::Duration};
+
+use bevy::prelude::*;
+use bevy_renet::renet::{DefaultChannel, RenetClient};
+use chrono::{Local, TimeZone};
+
+use crate::{
+    input::Actions,
+    messages::{clean_chat, ClientMessageReliable, MAX_CHAT_LENGTH},
+    run_if::run_if_client,
+    GameState,
+};
+
+// how long a message stays on screen after it arrives
+const CHAT_TIME: Duration = Duration::from_secs(8);
+// it fades out over the last this many seconds
+const CHAT_FADE: f32 = 2.0;
+// how many messages are kept, they are all shown while typing
+const CHAT_HISTORY: usize = 8;
+
+pub struct ChatPlugin;
+
+impl Plugin for ChatPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ChatInput>()
+            .init_resource::<ChatLog>()
+            .add_system(
+                chat_typing
+                    .in_set(OnUpdate(GameState::Gameplay))
+                    .run_if(run_if_client),
+            )
+            .add_system(close_chat.in_schedule(OnExit(GameState::Gameplay)))
+            .add_system(show_chat.after(chat_typing));
+    }
+}
+
+// the line the player types a message in
+#[derive(Resource, Default)]
+enum ChatInput {
+    #[default]
+    Closed,
+    Open(String),
+    // sent or cancelled. the controls stay off until enter or escape is let go
+    // so closing the chat doesn't also jump or pause
+    Closing,
+}
+
+// the last few messages from the server
+#[derive(Resource, Default)]
+pub struct ChatLog(VecDeque<(String, Timer)>);
+
+impl ChatLog {
+    // name is empty for messages from the server itself. time is seconds since 1970
+    pub fn push(&mut self, name: &str, text: &str, time: u64) {
+        let time = Local
+            .timestamp_opt(time as i64, 0)
+            .single()
+            .map_or(String::new(), |time| time.format("%H:%M ").to_string());
+        let line = if name.is_empty() {
+            format!("{time}{text}")
+        } else {
+            format!("{time}{name}: {text}")
+        };
+        println!("{line}");
+
+        self.0
+            .push_back((line, Timer::new(CHAT_TIME, TimerMode::Once)));
+        if self.0.len() > CHAT_HISTORY {
+            self.0.pop_front();
+        }
+    }
+}
+
+// t opens the chat, enter sends it and escape cancels it
+fn chat_typing(
+    mut char_evr: EventReader<ReceivedCharacter>,
+    keys: Res<Input<KeyCode>>,
+    mut input: ResMut<ChatInput>,
+    mut actions: ResMut<Actions>,
+    mut client: ResMut<RenetClient>,
+) {
+    let mut next = None;
+    match &mut *input {
+        ChatInput::Closed => {
+            // the t comes through as a character too, it shouldn't be typed
+            char_evr.clear();
+            if keys.just_pressed(KeyCode::T) {
+                next = Some(ChatInput::Open(String::new()));
+            }
+        }
+        ChatInput::Open(text) => {
+            for ev in char_evr.iter() {
+                if ev.char == '\x08' {
+                    text.pop();
+                } else if !ev.char.is_control() && text.chars().count() < MAX_CHAT_LENGTH {
+                    text.push(ev.char);
+                }
+            }
+
+            if keys.just_pressed(KeyCode::Return) {
+                let text = clean_chat(text);
+                if !text.is_empty() {
+                    client.send_message(
+                        DefaultChannel::Reliable,
+                        bincode::serialize(&ClientMessageReliable::Chat(text)).unwrap(),
+                    );
+                }
+                next = Some(ChatInput::Closing);
+            } else if keys.just_pressed(KeyCode::Escape) {
+                next = Some(ChatInput::Closing);
+            }
+        }
+        ChatInput::Closing => {
+            char_evr.clear();
+            if !keys.any_pressed([KeyCode::Return, KeyCode::Escape]) {
+                next = Some(ChatInput::Closed);
+            }
+        }
+    }
+
+    if let Some(next) = next {
+        *input = next;
+    }
+    actions.typing = !matches!(*input, ChatInput::Closed);
+}
+
+// dying or finishing the level while typing throws the message away
+fn close_chat(mut input: ResMut<ChatInput>, mut actions: ResMut<Actions>) {
+    *input = ChatInput::Closed;
+    actions.typing = false;
+}
+
+#[derive(Component)]
+struct ChatText;
+
+// new messages fade out in the bottom left corner. while typing every
+// message is shown with the line being typed under them
+fn show_chat(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut log: ResMut<ChatLog>,
+    input: Res<ChatInput>,
+    mut text: Query<(Entity, &mut Text), With<ChatText>>,
+    asset_server: Res<AssetServer>,
+) {
+    for (_, timer) in log.0.iter_mut() {
+        timer.tick(time.delta());
+    }
+
+    let font = asset_server.load("fonts/Rubik-SemiBold.ttf");
+    let section = |value: String, alpha: f32| {
+        TextSection::new(
+            value,
+            TextStyle {
+                font: font.clone(),
+                font_size: 25.0,
+                color: Color::rgba(0.0, 0.0, 0.0, alpha),
+            },
+        )
+    };
+
+    let typing = match &*input {
+        ChatInput::Open(text) => Some(text),
+        _ => None,
+    };
+    let mut sections: Vec<TextSection> = log
+        .0
+        .iter()
+        .filter(|(_, timer)| typing.is_some() || !timer.finished())
+        .map(|(line, timer)| {
+            let alpha = match typing {
+                Some(_) => 1.0,
+                None => (timer.remaining_secs() / CHAT_FADE).min(1.0),
+            };
+            section(format!("{line}\n"), alpha)
+        })
+        .collect();
+    if let Some(typing) = typing {
+        sections.push(section(format!("> {typing}_"), 1.0));
+    }
+
+    if sections.is_empty() {
+        for (entity, _) in text.iter() {
+            commands.entity(entity).despawn();
+        }
+        return;
+    }
+
+    if let Ok((_, mut text)) = text.get_single_mut() {
+        text.sections = sections;
+        return;
+    }
+
+    commands.spawn((
+        TextBundle::from_sections(sections).with_style(Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                bottom: Val::Px(10.0),
+                left: Val::Px(10.0),
+                ..default()
+            },
+            ..default()
+        }),
+        ChatText,
+    ));
+}
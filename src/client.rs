@@ -1,40 +1,73 @@
 use bevy::prelude::*;
-use bevy_rapier2d::prelude::{Collider, RigidBody};
+use bevy_rapier2d::prelude::{Collider, KinematicCharacterControllerOutput, RigidBody};
 use bevy_renet::{
     renet::{ClientAuthentication, DefaultChannel, RenetClient, RenetConnectionConfig},
     RenetClientPlugin,
 };
 
-use std::net::{IpAddr, SocketAddr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::{collections::HashMap, net::UdpSocket, time::SystemTime};
 
 use crate::{
-    messages::{ClientMessageUnreliable, ServerMessageUnreliable},
-    moving_block::BlockMap,
+    auth::{host_token, PrivateKey},
+    chat::ChatLog,
+    connection_lost::lose_connection,
+    grappling_hook::{hook_state, Hook, MovingGrappleHook},
+    interpolation::{InterpolationSettings, Snapshot, SnapshotBuffer},
+    join_menu::BindError,
+    messages::{
+        decode, name_to_user_data, ClientMessageReliable, ClientMessageUnreliable, Facing,
+        HookState, ServerMessageReliable, ServerMessageUnreliable, GAME_VERSION, PROTOCOL_ID,
+        PROTOCOL_VERSION,
+    },
+    moving_block::{BlockMap, SharedWall},
     player::Player,
-    server::{CLIENT_PORT, SERVER_PORT},
+    race::Race,
+    roster::{roster_event, RosterEvent},
+    run_if::run_if_client,
+    settings::Settings,
     startup_plugin::GameTextures,
-    CurrentLevel, GameState, FELLA_SPRITE_SIZE, run_if::run_if_client,
+    CurrentLevel, GameState, FELLA_SPRITE_SIZE, HOOK_SPRITE_SIZE,
 };
 
+// how the rope between another player and their hook is drawn
+const ROPE_WIDTH: f32 = 3.0;
+const ROPE_COLOUR: Color = Color::rgb(0.3, 0.2, 0.1);
+// how many client ports to try, one machine can run this many clients at once
+pub const CLIENT_PORT_TRIES: u16 = 16;
+
 pub struct MyClientPlugin;
 
 impl Plugin for MyClientPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugin(RenetClientPlugin::default())
             .insert_resource(UserIdMap(HashMap::new()))
+            .init_resource::<InterpolationSettings>()
+            .add_system(apply_interpolation_settings)
             // add the client system to run when in client mode
             .add_system(
                 client_update_system
                     .in_set(OnUpdate(GameState::Gameplay))
                     .run_if(run_if_client),
             )
+            // the results of a race keep coming in on the win screen
+            .add_system(
+                client_update_system
+                    .in_set(OnUpdate(GameState::Win))
+                    .run_if(run_if_client),
+            )
             // add the update player system to run when in client mode
             .add_system(
                 update_players
                     .in_set(OnUpdate(GameState::Gameplay))
                     .run_if(run_if_client),
             )
+            .add_system(
+                update_remote_hooks
+                    .after(update_players)
+                    .in_set(OnUpdate(GameState::Gameplay))
+                    .run_if(run_if_client),
+            )
             // add the "client_send_input" system when in client mode
             .add_system(client_send_input.run_if(run_if_client));
     }
@@ -48,52 +81,129 @@ pub struct AnotherPlayer {
     pub id: u64,
 }
 
+// another player's grappling hook
+#[derive(Component)]
+struct RemoteHook {
+    id: u64,
+}
+
+// the rope from another player to their hook
+#[derive(Component)]
+struct RemoteRope {
+    id: u64,
+}
+
 #[derive(Resource)]
-// a hashmap where the key is the userid, and the value is a tuple of the
-// player's position, the level that player is on and wether it needs to be spawned
-pub struct UserIdMap(pub HashMap<u64, (Vec3, u8, bool)>);
+// a hashmap where the key is the userid and the value is everything we know about that player
+pub struct UserIdMap(pub HashMap<u64, RemotePlayer>);
+
+pub struct RemotePlayer {
+    // the positions the server has sent us, used to draw the player smoothly
+    pub snapshots: SnapshotBuffer,
+    // the level that player is on
+    pub level: u8,
+    // wether they have been spawned
+    pub spawned: bool,
+}
 
-pub const PROTOCOL_ID: u64 = 6;
+// the address a client listens on, it has to be ipv6 to talk to an ipv6 server
+pub fn client_bind_address(server_ip: IpAddr, port: u16) -> SocketAddr {
+    let any = match server_ip {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    SocketAddr::new(any, port)
+}
 
-pub fn new_renet_client(number: u16, ip: IpAddr) -> RenetClient {
+// the host's own client. if the server is secure it makes itself a token with the key
+pub fn new_renet_client(
+    ip: IpAddr,
+    settings: &Settings,
+    private_key: Option<&PrivateKey>,
+) -> Result<RenetClient, BindError> {
     // the ip and port of the server
-    let server_addr = SocketAddr::new(ip, SERVER_PORT);
-    // the ip and port of the client
-    let client_addr = SocketAddr::new("0.0.0.0".parse().unwrap(), CLIENT_PORT + number);
-    // if the socket is valid
-    if let Ok(socket) = UdpSocket::bind(client_addr) {
+    let server_addr = SocketAddr::new(ip, settings.server_port);
+
+    // the port may already be being used if there are multiple
+    // instances of the game running at once, so the next ones are tried
+    for i in 0..CLIENT_PORT_TRIES {
+        // there are no ports past 65535
+        let Some(port) = settings.client_port.checked_add(i) else {
+            break;
+        };
+        // the ip and port of the client
+        let Ok(socket) = UdpSocket::bind(client_bind_address(ip, port)) else {
+            continue;
+        };
+
         let connection_config = RenetConnectionConfig::default();
         let current_time = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
             .unwrap();
         // generate a unique client id
         let client_id = current_time.as_millis() as u64;
-        // my server doesn't use authentication
-        let authentication = ClientAuthentication::Unsecure {
-            client_id,
-            protocol_id: PROTOCOL_ID,
-            server_addr,
-            user_data: None,
+        let authentication = match private_key {
+            Some(key) => ClientAuthentication::Secure {
+                connect_token: host_token(key, server_addr, client_id, &settings.player_name),
+            },
+            None => ClientAuthentication::Unsecure {
+                client_id,
+                protocol_id: PROTOCOL_ID,
+                server_addr,
+                // the server reads our display name from this
+                user_data: Some(name_to_user_data(&settings.player_name)),
+            },
         };
         // generates the new client and returns it
-        RenetClient::new(current_time, socket, connection_config, authentication).unwrap()
-    } else {
-        // recursively increases the number as the port may already be being used
-        // this can occur if there are multiple instances of the game running at once
-        new_renet_client(number + 1, ip)
+        let mut client =
+            RenetClient::new(current_time, socket, connection_config, authentication).unwrap();
+        send_hello(&mut client);
+        return Ok(client);
     }
+    Err(BindError::Client)
 }
 
-// send the player's position and the level they are in to the server
+// tells the server which version of the game we are. it has to be the first
+// message sent, the server ignores everything else until it has seen it
+pub fn send_hello(client: &mut RenetClient) {
+    let message = ClientMessageReliable::Hello {
+        protocol: PROTOCOL_VERSION,
+        game_version: GAME_VERSION.to_string(),
+    };
+    client.send_message(
+        DefaultChannel::Reliable,
+        bincode::serialize(&message).unwrap(),
+    );
+}
+
+// send the player's position, what they are doing and the level they are in to the server
 fn client_send_input(
     mut client: ResMut<RenetClient>,
-    player_position: Query<&Transform, With<Player>>,
+    player: Query<(
+        &Transform,
+        &Player,
+        Option<&KinematicCharacterControllerOutput>,
+    )>,
+    hooks: Query<(&Transform, Option<&MovingGrappleHook>), (With<Hook>, Without<Player>)>,
     level: Res<CurrentLevel>,
+    time: Res<Time>,
 ) {
-    for pos in player_position.iter() {
+    for (pos, player, output) in player.iter() {
+        // how far rapier actually moved the player last frame, so walls are taken into account
+        let velocity = match output {
+            Some(output) if time.delta_seconds() > 0.0 => {
+                output.effective_translation / time.delta_seconds()
+            }
+            _ => Vec2::ZERO,
+        };
+
         let message = ClientMessageUnreliable::PlayerPosition {
             pos: pos.translation,
             level: level.level_number,
+            time: time.elapsed_seconds_f64(),
+            velocity,
+            facing: player.facing,
+            hook: hook_state(hooks.get_single().ok()),
         };
         let input_message = bincode::serialize(&message).unwrap();
 
@@ -101,14 +211,24 @@ fn client_send_input(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn client_update_system(
     mut client: ResMut<RenetClient>,
     mut player_map: ResMut<UserIdMap>,
     mut block_map: ResMut<BlockMap>,
+    mut race: ResMut<Race>,
+    time: Res<Time>,
+    mut commands: Commands,
+    mut game_state: ResMut<NextState<GameState>>,
+    mut roster: EventWriter<RosterEvent>,
+    mut chat: ResMut<ChatLog>,
 ) {
     // iterate over every message
     while let Some(message) = client.receive_message(DefaultChannel::Unreliable) {
-        let server_message: ServerMessageUnreliable = bincode::deserialize(&message).unwrap();
+        // skip anything that can't be read
+        let Some(server_message) = decode::<ServerMessageUnreliable>(&message) else {
+            continue;
+        };
 
         match server_message {
             // player position
@@ -116,84 +236,157 @@ fn client_update_system(
                 id,
                 position: pos,
                 level,
+                time: sent,
+                velocity,
+                facing,
+                hook,
             } => {
-                // update the positions and level numbers if it exists
-                if let Some((position, level_number, _)) = player_map.0.get_mut(&id) {
-                    *position = pos;
-                    *level_number = level;
-                } else {
-                    // insert the information - pos - level - has not been spawned yet
-                    player_map.0.insert(id, (pos, level, false));
+                // insert the player if we haven't heard from them before. they haven't been spawned yet
+                let player = player_map.0.entry(id).or_insert_with(|| RemotePlayer {
+                    snapshots: SnapshotBuffer::new(),
+                    level,
+                    spawned: false,
+                });
+
+                // don't slide across from where they were on the last level
+                if player.level != level {
+                    player.level = level;
+                    player.snapshots = SnapshotBuffer::new();
                 }
+
+                let snapshot = Snapshot {
+                    time: sent,
+                    position: pos,
+                    velocity,
+                    facing,
+                    hook,
+                };
+                player.snapshots.push(snapshot, time.elapsed_seconds_f64());
             }
             // When the client recieves a message from the server with the wall position,
-            // it adds it to the hashmap
+            // it adds it to the hashmap. only the wall's owner sends positions
             ServerMessageUnreliable::WallPos {
                 level,
-                client_id,
                 wall_id,
                 pos,
             } => {
-                // hashmap key is the level so the client can easily find the right blocks
-                // if there are some blocks in this level
-                if let Some(existing_blocks) = block_map.blocks.get_mut(&level) {
-                    let mut t = false;
-                    // iterate over these blocks and see if they match the message recieved by the server
-                    for i in &mut *existing_blocks {
-                        // if the block matches
-                        if i.0 == client_id && i.1 == wall_id {
-                            // update position
-                            i.2 = pos;
-                            t = true;
-                            break;
-                        }
-                    }
-                    // if none match, create another block
-                    if !t {
-                        existing_blocks.push((client_id, wall_id, pos))
-                    }
-                } else {
-                    let vec = vec![(client_id, wall_id, pos)];
-                    block_map.blocks.insert(level, vec);
+                // hashmap key is the level and wall so the client can easily find the right block
+                let wall = block_map
+                    .blocks
+                    .entry((level, wall_id))
+                    .or_insert(SharedWall {
+                        pos: None,
+                        owner: None,
+                    });
+                wall.pos = Some(pos);
+            }
+        }
+    }
+
+    while let Some(message) = client.receive_message(DefaultChannel::Reliable) {
+        let Some(server_message) = decode::<ServerMessageReliable>(&message) else {
+            continue;
+        };
+
+        // someone joined or left
+        if let Some(event) = roster_event(&server_message) {
+            roster.send(event);
+            continue;
+        }
+
+        match server_message {
+            // the server decided who is allowed to move a wall
+            ServerMessageReliable::WallOwner {
+                level,
+                wall_id,
+                owner,
+                pos,
+            } => {
+                let wall = block_map
+                    .blocks
+                    .entry((level, wall_id))
+                    .or_insert(SharedWall {
+                        pos: None,
+                        owner: None,
+                    });
+                wall.owner = owner;
+                if pos.is_some() {
+                    wall.pos = pos;
                 }
             }
+            ServerMessageReliable::Countdown(count) => race.countdown = Some(count),
+            ServerMessageReliable::RaceStart => race.start(),
+            ServerMessageReliable::Standings(standings) => race.standings = standings,
+            ServerMessageReliable::Chat {
+                name, text, time, ..
+            } => chat.push(&name, &text, time),
+            // eg kicked for cheating
+            ServerMessageReliable::Rejected { reason } => {
+                lose_connection(&mut commands, &mut game_state, reason);
+                return;
+            }
             _ => (),
         }
     }
 }
 
+// uses the interpolation settings from the settings file, and again whenever they change
+fn apply_interpolation_settings(
+    settings: Res<Settings>,
+    mut interpolation: ResMut<InterpolationSettings>,
+) {
+    if settings.is_changed() {
+        *interpolation = settings.interpolation;
+    }
+}
+
 fn update_players(
     mut player_map: ResMut<UserIdMap>,
     gt: Res<GameTextures>,
     cl: Res<CurrentLevel>,
     mut commands: Commands,
-    mut players: Query<(Entity, &AnotherPlayer, &mut Transform)>,
+    mut players: Query<(Entity, &AnotherPlayer, &mut Transform, &mut Sprite)>,
+    time: Res<Time>,
+    settings: Res<InterpolationSettings>,
 ) {
+    let now = time.elapsed_seconds_f64();
+
     // iterate over all the spawned players
-    for (entity, ap, mut transform) in players.iter_mut() {
-        // get the info sent by the server
-        let player_info = player_map.0.get_mut(&ap.id).unwrap();
+    for (entity, ap, mut transform, mut sprite) in players.iter_mut() {
+        // get the info sent by the server. they have left if there isn't any
+        let Some(player_info) = player_map.0.get_mut(&ap.id) else {
+            commands.entity(entity).despawn();
+            continue;
+        };
         // if the player is on the same level as the client
-        if player_info.1 == cl.level_number {
-            // update its position
-            transform.translation = player_info.0;
+        if player_info.level == cl.level_number {
+            // draw it a little in the past so it moves smoothly between snapshots
+            if let Some(position) = player_info.snapshots.sample(now, &settings) {
+                transform.translation = position;
+            }
+            if let Some(state) = player_info.snapshots.state_at(now, &settings) {
+                sprite.flip_x = state.facing == Facing::Left;
+            }
         } else {
             // despawn it
             commands.entity(entity).despawn();
             // it has no longer been spawned so set this to false
-            player_info.2 = false;
+            player_info.spawned = false;
         }
     }
 
     for (id, value) in player_map.0.iter_mut() {
         // iterate over only the ones which havent been spawned
-        if value.2 {
+        if value.spawned {
             continue;
         }
         // ignore players on different levels
-        if value.1 != cl.level_number {
+        if value.level != cl.level_number {
             continue;
         }
+        let Some(position) = value.snapshots.sample(now, &settings) else {
+            continue;
+        };
         // if the player is on this level and hasn't been spawned yet
         // spawn the player
         commands
@@ -203,7 +396,7 @@ fn update_players(
                     custom_size: Some(FELLA_SPRITE_SIZE),
                     ..Default::default()
                 },
-                transform: Transform::from_translation(value.0),
+                transform: Transform::from_translation(position),
                 ..Default::default()
             })
             .insert(Collider::cuboid(
@@ -214,6 +407,114 @@ fn update_players(
             .insert(AnotherPlayer { id: *id });
 
         // it has now been spawned so set this to true
-        value.2 = true;
+        value.spawned = true;
+    }
+}
+
+// draws the other players' grappling hooks and the ropes back to them
+#[allow(clippy::too_many_arguments)]
+fn update_remote_hooks(
+    player_map: Res<UserIdMap>,
+    gt: Res<GameTextures>,
+    cl: Res<CurrentLevel>,
+    time: Res<Time>,
+    settings: Res<InterpolationSettings>,
+    mut commands: Commands,
+    players: Query<(&AnotherPlayer, &Transform)>,
+    mut hooks: Query<(Entity, &RemoteHook, &mut Transform), Without<AnotherPlayer>>,
+    mut ropes: Query<
+        (Entity, &RemoteRope, &mut Transform, &mut Sprite),
+        (Without<AnotherPlayer>, Without<RemoteHook>),
+    >,
+) {
+    let now = time.elapsed_seconds_f64();
+
+    // where each player is and where their hook is, for the players that have one out
+    let mut out = HashMap::new();
+    for (ap, transform) in players.iter() {
+        let Some(info) = player_map.0.get(&ap.id) else {
+            continue;
+        };
+        if info.level != cl.level_number {
+            continue;
+        }
+        let Some(state) = info.snapshots.state_at(now, &settings) else {
+            continue;
+        };
+        let hook = match state.hook {
+            HookState::None => continue,
+            HookState::Flying { position, .. } => position,
+            HookState::Attached { anchor } => anchor,
+        };
+        out.insert(ap.id, (transform.translation.truncate(), hook));
+    }
+
+    // move the hooks that are already drawn, and remove the ones that have gone
+    let mut drawn_hooks = vec![];
+    for (entity, hook, mut transform) in hooks.iter_mut() {
+        let Some((player, position)) = out.get(&hook.id) else {
+            commands.entity(entity).despawn();
+            continue;
+        };
+        transform.translation = position.extend(11.0);
+        // point it away from the player like our own hook
+        transform.rotation = Quat::from_rotation_z(Vec2::Y.angle_between(*position - *player));
+        drawn_hooks.push(hook.id);
+    }
+
+    let mut drawn_ropes = vec![];
+    for (entity, rope, mut transform, mut sprite) in ropes.iter_mut() {
+        let Some((player, position)) = out.get(&rope.id) else {
+            commands.entity(entity).despawn();
+            continue;
+        };
+        *transform = rope_transform(*player, *position);
+        sprite.custom_size = Some(Vec2::new(ROPE_WIDTH, player.distance(*position)));
+        drawn_ropes.push(rope.id);
+    }
+
+    // spawn the ones that have just come out
+    for (id, (player, position)) in out.iter() {
+        if !drawn_hooks.contains(id) {
+            commands
+                .spawn(SpriteBundle {
+                    texture: gt.hook.clone(),
+                    sprite: Sprite {
+                        custom_size: Some(HOOK_SPRITE_SIZE),
+                        ..Default::default()
+                    },
+                    transform: Transform {
+                        translation: position.extend(11.0),
+                        rotation: Quat::from_rotation_z(Vec2::Y.angle_between(*position - *player)),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .insert(RemoteHook { id: *id });
+        }
+        if !drawn_ropes.contains(id) {
+            commands
+                .spawn(SpriteBundle {
+                    sprite: Sprite {
+                        color: ROPE_COLOUR,
+                        custom_size: Some(Vec2::new(ROPE_WIDTH, player.distance(*position))),
+                        ..Default::default()
+                    },
+                    transform: rope_transform(*player, *position),
+                    ..Default::default()
+                })
+                .insert(RemoteRope { id: *id });
+        }
+    }
+}
+
+// a rope sprite is a thin rectangle, so put it halfway between the
+// player and the hook and turn it to point along the line between them
+fn rope_transform(player: Vec2, hook: Vec2) -> Transform {
+    Transform {
+        // behind the hook and the player
+        translation: ((player + hook) / 2.0).extend(10.0),
+        rotation: Quat::from_rotation_z(Vec2::Y.angle_between(hook - player)),
+        ..Default::default()
     }
 }
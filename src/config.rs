@@ -0,0 +1,32 @@
+use std::{fs, path::PathBuf};
+
+use serde::Serialize;
+
+// the folder the game keeps the player's settings in, eg ~/.config/multiplayer_platformer
+// on linux. if the system doesn't have one they go in a config folder next to the game
+pub fn config_directory() -> PathBuf {
+    dirs::config_dir()
+        .map(|dir| dir.join("multiplayer_platformer"))
+        .unwrap_or_else(|| PathBuf::from("config"))
+}
+
+// the path of a file in the config folder
+pub fn config_path(file: &str) -> PathBuf {
+    config_directory().join(file)
+}
+
+// writes a file to the config folder, making the folder if it isn't there yet
+pub fn save_config(file: &str, contents: &str) -> Result<(), String> {
+    let path = config_path(file);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("could not create {}: {e}", dir.display()))?;
+    }
+    fs::write(&path, contents).map_err(|e| format!("could not write {}: {e}", path.display()))
+}
+
+// writes a value to a .ron file in the config folder
+pub fn save_ron<T: Serialize>(file: &str, value: &T) -> Result<(), String> {
+    let contents = ron::ser::to_string_pretty(value, ron::ser::PrettyConfig::new())
+        .map_err(|e| e.to_string())?;
+    save_config(file, &contents)
+}
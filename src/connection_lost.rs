@@ -0,0 +1,255 @@
+use bevy::prelude::*;
+use bevy_renet::renet::{RenetClient, RenetError};
+
+use crate::{
+    join_menu::{cancel_join, connect, poll_join, LastServer, PendingJoin},
+    main_menu::HostClient,
+    moving_block::BlockMap,
+    reconnect::Reconnecting,
+    settings::Settings,
+    startup_plugin::despawn_everything,
+    CurrentLevel, GameState, MultiplayerSetting, BACKGROUND_COLOUR,
+};
+
+pub struct ConnectionLostPlugin;
+
+impl Plugin for ConnectionLostPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(handle_renet_errors)
+            .add_system(setup_connection_lost.in_schedule(OnEnter(GameState::ConnectionLost)))
+            .add_system(connection_lost_input.in_set(OnUpdate(GameState::ConnectionLost)))
+            .add_system(finish_retry.in_set(OnUpdate(GameState::ConnectionLost)))
+            .add_system(cancel_join.in_schedule(OnExit(GameState::ConnectionLost)))
+            .add_system(update_connection_lost_text.in_set(OnUpdate(GameState::ConnectionLost)))
+            .add_system(despawn_everything.in_schedule(OnExit(GameState::ConnectionLost)));
+    }
+}
+
+// why the connection was lost, and why retrying didn't work if it didn't
+#[derive(Resource)]
+pub struct ConnectionLost {
+    reason: String,
+    retry_error: String,
+}
+
+// drops the connection and shows the player why
+pub fn lose_connection(
+    commands: &mut Commands,
+    game_state: &mut NextState<GameState>,
+    reason: String,
+) {
+    println!("lost the connection to the server: {reason}");
+    commands.insert_resource(ConnectionLost {
+        reason,
+        retry_error: String::new(),
+    });
+    commands.insert_resource(MultiplayerSetting(HostClient::Play));
+    commands.remove_resource::<RenetClient>();
+    commands.remove_resource::<Reconnecting>();
+    game_state.set(GameState::ConnectionLost);
+}
+
+// a client that loses the server in the middle of a level tries to get back in,
+// otherwise it goes to the connection lost screen. the host keeps running,
+// renet drops a client that goes wrong by itself
+fn handle_renet_errors(
+    mut errors: EventReader<RenetError>,
+    setting: Res<MultiplayerSetting>,
+    state: Res<State<GameState>>,
+    reconnecting: Option<ResMut<Reconnecting>>,
+    last_server: Option<Res<LastServer>>,
+    mut commands: Commands,
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    // the first error says why, the rest are about the same connection
+    let mut reason = None;
+    for e in errors.iter() {
+        match setting.0 {
+            HostClient::Client => {
+                reason.get_or_insert_with(|| e.to_string());
+            }
+            HostClient::Host | HostClient::Play => println!("network error: {e}"),
+        }
+    }
+
+    let Some(reason) = reason else {
+        return;
+    };
+    match reconnecting {
+        // that attempt didn't work either
+        Some(mut reconnecting) => {
+            commands.remove_resource::<RenetClient>();
+            if !reconnecting.schedule_next() {
+                lose_connection(&mut commands, &mut game_state, reason);
+            }
+        }
+        None if state.0 == GameState::Gameplay && last_server.is_some() => {
+            println!("lost the connection to the server: {reason}, reconnecting");
+            commands.remove_resource::<RenetClient>();
+            commands.insert_resource(Reconnecting::default());
+        }
+        None => lose_connection(&mut commands, &mut game_state, reason),
+    }
+}
+
+#[derive(Component)]
+struct ConnectionLostText;
+
+#[derive(Component, Clone, Copy)]
+enum LostButton {
+    Retry,
+    Menu,
+}
+
+fn setup_connection_lost(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut block_map: ResMut<BlockMap>,
+    mut current_level: ResMut<CurrentLevel>,
+) {
+    // a retry starts from the beginning
+    block_map.blocks.clear();
+    current_level.level_number = 1;
+
+    commands.insert_resource(ClearColor(BACKGROUND_COLOUR));
+    commands.spawn(Camera2dBundle::default());
+
+    let style = TextStyle {
+        font: asset_server.load("fonts/Rubik-SemiBold.ttf"),
+        font_size: 40.0,
+        color: Color::BLACK,
+    };
+
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                flex_direction: FlexDirection::Column,
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_sections([
+                    TextSection::new(
+                        "Connection lost\n\n",
+                        TextStyle {
+                            font_size: 60.0,
+                            ..style.clone()
+                        },
+                    ),
+                    TextSection::new("", style.clone()),
+                ]),
+                ConnectionLostText,
+            ));
+
+            for (button, name) in [(LostButton::Retry, "RETRY"), (LostButton::Menu, "MENU")] {
+                parent
+                    .spawn((
+                        ButtonBundle {
+                            style: Style {
+                                padding: UiRect::all(Val::Px(5.0)),
+                                ..default()
+                            },
+                            background_color: Color::NONE.into(),
+                            ..default()
+                        },
+                        button,
+                    ))
+                    .with_children(|parent| {
+                        parent.spawn(TextBundle::from_section(
+                            name,
+                            TextStyle {
+                                font_size: 60.0,
+                                ..style.clone()
+                            },
+                        ));
+                    });
+            }
+
+            parent.spawn(TextBundle::from_section(
+                "Enter: retry   Escape: menu",
+                style.clone(),
+            ));
+        });
+}
+
+fn connection_lost_input(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    mut buttons: Query<(&Interaction, &LostButton, &mut BackgroundColor), Changed<Interaction>>,
+    mut lost: ResMut<ConnectionLost>,
+    last_server: Option<Res<LastServer>>,
+    mut settings: ResMut<Settings>,
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    let mut pressed = None;
+    if keys.just_pressed(KeyCode::Return) {
+        pressed = Some(LostButton::Retry);
+    }
+    if keys.just_pressed(KeyCode::Escape) {
+        pressed = Some(LostButton::Menu);
+    }
+    for (interaction, button, mut colour) in buttons.iter_mut() {
+        match interaction {
+            Interaction::Clicked => pressed = Some(*button),
+            Interaction::Hovered => *colour = Color::rgba(1.0, 1.0, 1.0, 0.4).into(),
+            Interaction::None => *colour = Color::NONE.into(),
+        }
+    }
+
+    match pressed {
+        Some(LostButton::Retry) => {
+            let Some(last_server) = last_server else {
+                lost.retry_error = "there is no server to retry".to_string();
+                return;
+            };
+            // goes to the connection check if it works. the same session
+            // means the server remembers where we were
+            let target = last_server.target.clone();
+            let session = last_server.session;
+            if let Err(e) = connect(
+                &target,
+                session,
+                &mut commands,
+                &mut game_state,
+                &mut settings,
+            ) {
+                lost.retry_error = e.to_string();
+            }
+        }
+        Some(LostButton::Menu) => game_state.set(GameState::Menu),
+        None => (),
+    }
+}
+
+// retries once the server's hostname has been looked up
+fn finish_retry(
+    mut commands: Commands,
+    pending: Option<Res<PendingJoin>>,
+    mut lost: ResMut<ConnectionLost>,
+    mut settings: ResMut<Settings>,
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    let Some(pending) = pending else {
+        return;
+    };
+    if pending.is_added() {
+        lost.retry_error = format!("looking up {}...", pending.lookup.host);
+    }
+    if let Some(Err(e)) = poll_join(&pending, &mut commands, &mut game_state, &mut settings) {
+        lost.retry_error = e.to_string();
+    }
+}
+
+fn update_connection_lost_text(
+    lost: Res<ConnectionLost>,
+    mut text: Query<&mut Text, With<ConnectionLostText>>,
+) {
+    if !lost.is_changed() {
+        return;
+    }
+    for mut text in &mut text {
+        text.sections[1].value = format!("{}\n{}\n", lost.reason, lost.retry_error);
+    }
+}
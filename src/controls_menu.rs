@@ -0,0 +1,140 @@
+use bevy::prelude::*;
+
+use crate::{
+    input::{Action, Binding, Bindings},
+    startup_plugin::despawn_everything,
+    GameState, BACKGROUND_COLOUR,
+};
+
+pub struct ControlsMenuPlugin;
+
+impl Plugin for ControlsMenuPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ControlsMenu::default())
+            .add_system(setup_controls_menu.in_schedule(OnEnter(GameState::Controls)))
+            .add_system(controls_input.in_set(OnUpdate(GameState::Controls)))
+            .add_system(update_controls_text.in_set(OnUpdate(GameState::Controls)))
+            .add_system(despawn_everything.in_schedule(OnExit(GameState::Controls)));
+    }
+}
+
+// which action is selected and whether we are waiting for its new key
+#[derive(Resource, Default)]
+struct ControlsMenu {
+    selected: usize,
+    waiting: bool,
+}
+
+#[derive(Component)]
+struct ControlsText;
+
+fn setup_controls_menu(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(ControlsMenu::default());
+    commands.insert_resource(ClearColor(BACKGROUND_COLOUR));
+    commands.spawn(Camera2dBundle::default());
+
+    let style = TextStyle {
+        font: asset_server.load("fonts/Rubik-SemiBold.ttf"),
+        font_size: 40.0,
+        color: Color::BLACK,
+    };
+
+    // a title, a line for each action and the help at the bottom
+    let mut sections = vec![TextSection::new("Controls\n\n", style.clone())];
+    sections.extend(
+        Action::ALL
+            .iter()
+            .map(|_| TextSection::new("", style.clone())),
+    );
+    sections.push(TextSection::new("", style));
+
+    commands.spawn((TextBundle::from_sections(sections), ControlsText));
+}
+
+fn controls_input(
+    keys: Res<Input<KeyCode>>,
+    mouse: Res<Input<MouseButton>>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    mut menu: ResMut<ControlsMenu>,
+    mut bindings: ResMut<Bindings>,
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    if menu.waiting {
+        // the first key or button pressed becomes the new binding, escape included
+        let binding = keys
+            .get_just_pressed()
+            .next()
+            .map(|key| Binding::Key(*key))
+            .or_else(|| mouse.get_just_pressed().next().map(|b| Binding::Mouse(*b)))
+            .or_else(|| {
+                gamepad_buttons
+                    .get_just_pressed()
+                    .next()
+                    .map(|b| Binding::Gamepad(b.button_type))
+            });
+
+        if let Some(binding) = binding {
+            bindings.rebind(Action::ALL[menu.selected], binding);
+            bindings.save();
+            menu.waiting = false;
+        }
+        return;
+    }
+
+    if keys.just_pressed(KeyCode::Up) {
+        menu.selected = (menu.selected + Action::ALL.len() - 1) % Action::ALL.len();
+    }
+    if keys.just_pressed(KeyCode::Down) {
+        menu.selected = (menu.selected + 1) % Action::ALL.len();
+    }
+    if keys.just_pressed(KeyCode::Return) {
+        menu.waiting = true;
+    }
+    // put everything back how it was
+    if keys.just_pressed(KeyCode::Back) {
+        *bindings = Bindings::default();
+        bindings.save();
+    }
+    if keys.just_pressed(KeyCode::Escape) {
+        game_state.set(GameState::Menu);
+    }
+}
+
+fn update_controls_text(
+    menu: Res<ControlsMenu>,
+    bindings: Res<Bindings>,
+    mut text: Query<&mut Text, With<ControlsText>>,
+) {
+    for mut text in &mut text {
+        for (i, action) in Action::ALL.iter().enumerate() {
+            let section = &mut text.sections[i + 1];
+
+            let value = if menu.waiting && i == menu.selected {
+                format!("{}: press a key or button...\n", action.name())
+            } else {
+                let names: Vec<String> = bindings
+                    .get(*action)
+                    .iter()
+                    .map(|binding| binding.to_string())
+                    .collect();
+                format!("{}: {}\n", action.name(), names.join(", "))
+            };
+            if section.value != value {
+                section.value = value;
+            }
+
+            // the selected action is white like the main menu
+            section.style.color = if i == menu.selected {
+                Color::WHITE
+            } else {
+                Color::BLACK
+            };
+        }
+
+        let help = "\nUp/Down: choose   Enter: change   Backspace: reset all   Escape: back";
+        let last = text.sections.len() - 1;
+        if text.sections[last].value != help {
+            text.sections[last].value = help.to_string();
+        }
+    }
+}
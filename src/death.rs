@@ -1,6 +1,7 @@
 use bevy::prelude::*;
 
 use crate::{
+    input::{Action, Actions},
     startup_plugin::{despawn_everything, GameTextures},
     GameState, BACKGROUND_COLOUR,
 };
@@ -31,9 +32,9 @@ fn setup_death(mut commands: Commands, game_textures: Res<GameTextures>) {
     });
 }
 
-fn restart(keys: Res<Input<KeyCode>>, mut game_state: ResMut<NextState<GameState>>) {
-    // go back to gameplay when r is pressed
-    if keys.just_pressed(KeyCode::R) {
+fn restart(actions: Res<Actions>, mut game_state: ResMut<NextState<GameState>>) {
+    // go back to gameplay when respawn (r) is pressed
+    if actions.just_pressed(Action::Respawn) {
         game_state.set(GameState::Gameplay);
     }
 }
@@ -0,0 +1,230 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    time::Duration,
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    messages::{ProtocolVersion, PROTOCOL_VERSION},
+    platform::Maps,
+    server::{ServerClients, ServerLobby, MAX_CLIENTS},
+};
+
+// servers listen for discovery queries on this port
+pub const DISCOVERY_PORT: u16 = 42070;
+
+// every discovery packet starts with this so other programs' broadcasts are ignored
+const DISCOVERY_MAGIC: &[u8; 8] = b"mpplatfm";
+
+// how often the join menu asks for servers
+const QUERY_INTERVAL: Duration = Duration::from_secs(1);
+
+// a server that hasn't answered for this long is taken off the list
+const SERVER_TIMEOUT: f64 = 3.0;
+
+// the biggest answer we expect, big map packs are cut off
+const MAX_PACKET_SIZE: usize = 2048;
+
+#[derive(Debug, Serialize, Deserialize)]
+enum DiscoveryPacket {
+    // sent by the join menu to every machine on the network
+    Query,
+    // sent back by servers
+    Answer(ServerInfo),
+}
+
+// what a server says about itself when it is found
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServerInfo {
+    pub name: String,
+    pub players: u32,
+    pub max_players: u32,
+    // the name of every map in order
+    pub map_pack: Vec<String>,
+    pub protocol: ProtocolVersion,
+    // the address to connect to, the same one the host is told to give out
+    pub address: SocketAddr,
+}
+
+fn encode(packet: &DiscoveryPacket) -> Vec<u8> {
+    let mut bytes = DISCOVERY_MAGIC.to_vec();
+    bytes.extend(bincode::serialize(packet).unwrap());
+    bytes
+}
+
+// anything that isn't one of our packets is ignored without printing,
+// broadcasts from other programs are normal
+fn decode_packet(bytes: &[u8]) -> Option<DiscoveryPacket> {
+    let body = bytes.strip_prefix(DISCOVERY_MAGIC)?;
+    bincode::deserialize(body).ok()
+}
+
+// the socket a server answers queries on
+#[derive(Resource)]
+pub struct DiscoveryResponder {
+    socket: UdpSocket,
+    // the address players connect to
+    address: SocketAddr,
+}
+
+impl DiscoveryResponder {
+    // None if the port is taken, eg by another server on the same machine.
+    // the server still works, it just can't be found
+    pub fn bind(address: SocketAddr) -> Option<Self> {
+        let bind_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), DISCOVERY_PORT);
+        let socket = match UdpSocket::bind(bind_addr) {
+            Ok(socket) => socket,
+            Err(e) => {
+                println!("lan discovery is off, could not listen on {bind_addr}: {e}");
+                return None;
+            }
+        };
+        socket.set_nonblocking(true).ok()?;
+        Some(DiscoveryResponder { socket, address })
+    }
+}
+
+// answers every query that arrived since the last update
+pub fn answer_discovery(
+    responder: Option<Res<DiscoveryResponder>>,
+    clients: Res<ServerClients>,
+    lobby: Res<ServerLobby>,
+    maps: Res<Maps>,
+) {
+    let Some(responder) = responder else {
+        return;
+    };
+
+    let mut buffer = [0; MAX_PACKET_SIZE];
+    while let Ok((size, from)) = responder.socket.recv_from(&mut buffer) {
+        if !matches!(decode_packet(&buffer[..size]), Some(DiscoveryPacket::Query)) {
+            continue;
+        }
+
+        // a hosted game is named after the host, a dedicated server has no host
+        let name = match lobby.host.and_then(|host| clients.0.get(&host)) {
+            Some(host) => format!("{}'s game", host.name),
+            None => "Dedicated server".to_string(),
+        };
+
+        let mut info = ServerInfo {
+            name,
+            players: clients.0.len() as u32,
+            max_players: MAX_CLIENTS as u32,
+            map_pack: maps.names(),
+            protocol: PROTOCOL_VERSION,
+            address: responder.address,
+        };
+
+        // leave maps off the end until it fits in one packet
+        let mut answer = encode(&DiscoveryPacket::Answer(info.clone()));
+        while answer.len() > MAX_PACKET_SIZE && info.map_pack.pop().is_some() {
+            answer = encode(&DiscoveryPacket::Answer(info.clone()));
+        }
+        let _ = responder.socket.send_to(&answer, from);
+    }
+}
+
+// a server the join menu has heard from
+pub struct FoundServer {
+    pub info: ServerInfo,
+    // when it last answered
+    last_seen: f64,
+}
+
+// asks the network for servers while the join menu is open
+#[derive(Resource)]
+pub struct LanDiscovery {
+    socket: Option<UdpSocket>,
+    timer: Timer,
+    // sorted by name so the list doesn't jump around
+    pub servers: Vec<FoundServer>,
+}
+
+impl LanDiscovery {
+    pub fn start() -> Self {
+        let socket = UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0))
+            .and_then(|socket| {
+                socket.set_broadcast(true)?;
+                socket.set_nonblocking(true)?;
+                Ok(socket)
+            });
+        if let Err(e) = &socket {
+            println!("could not search for lan servers: {e}");
+        }
+
+        let mut timer = Timer::new(QUERY_INTERVAL, TimerMode::Repeating);
+        // ask straight away
+        timer.set_elapsed(QUERY_INTERVAL);
+
+        LanDiscovery {
+            socket: socket.ok(),
+            timer,
+            servers: vec![],
+        }
+    }
+}
+
+// sends queries and collects the answers. the list is only marked as
+// changed when a server is found or lost so the menu isn't rebuilt every frame
+pub fn search_for_servers(time: Res<Time>, mut discovery: ResMut<LanDiscovery>) {
+    let now = time.elapsed_seconds_f64();
+    let search = discovery.bypass_change_detection();
+    let Some(socket) = &search.socket else {
+        return;
+    };
+
+    search.timer.tick(time.delta());
+    if search.timer.just_finished() {
+        let query = encode(&DiscoveryPacket::Query);
+        // broadcast for other machines, loopback for servers on this one
+        for ip in [Ipv4Addr::BROADCAST, Ipv4Addr::LOCALHOST] {
+            // this fails if there is no network at all, which is fine
+            let _ = socket.send_to(&query, SocketAddr::new(IpAddr::V4(ip), DISCOVERY_PORT));
+        }
+    }
+
+    let mut changed = false;
+    let mut buffer = [0; MAX_PACKET_SIZE];
+    while let Ok((size, _)) = socket.recv_from(&mut buffer) {
+        let Some(DiscoveryPacket::Answer(info)) = decode_packet(&buffer[..size]) else {
+            continue;
+        };
+
+        // the same server can answer on loopback and the lan
+        match search
+            .servers
+            .iter_mut()
+            .find(|s| s.info.address == info.address)
+        {
+            Some(server) => {
+                if server.info != info {
+                    server.info = info;
+                    changed = true;
+                }
+                server.last_seen = now;
+            }
+            None => {
+                search.servers.push(FoundServer {
+                    info,
+                    last_seen: now,
+                });
+                changed = true;
+            }
+        }
+    }
+
+    // forget servers that have shut down
+    let count = search.servers.len();
+    search
+        .servers
+        .retain(|server| now - server.last_seen < SERVER_TIMEOUT);
+    changed |= search.servers.len() != count;
+
+    if changed {
+        search.servers.sort_by(|a, b| a.info.name.cmp(&b.info.name));
+        discovery.set_changed();
+    }
+}
@@ -0,0 +1,550 @@
+use bevy::{prelude::*, window::PrimaryWindow};
+use bevy_rapier2d::prelude::*;
+
+use crate::{
+    level::{save_level, validate_level, Level, LevelError, Tile},
+    main_menu::{HostClient, MenuMessage},
+    moving_block::MovableWall,
+    platform::{
+        create_killer_wall, create_level_end, create_movable_wall, create_wall, level_directory,
+        Goal, KillerWall, Maps, Wall,
+    },
+    startup_plugin::{despawn_everything, GameTextures},
+    CurrentLevel, GameState, BACKGROUND_COLOUR, FELLA_SPRITE_SIZE, MAP_SCALE,
+};
+
+// how fast the camera moves with the arrow keys (pixels per second)
+const EDITOR_CAMERA_SPEED: f32 = 800.0;
+
+// the tiles in the palette, in the order of the number keys
+const PALETTE: [Tile; 6] = [
+    Tile::Empty,
+    Tile::Wall,
+    Tile::MovableWall,
+    Tile::Spawn,
+    Tile::KillerWall,
+    Tile::Goal,
+];
+
+pub struct EditorPlugin;
+
+impl Plugin for EditorPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(EditorPlaytest(false))
+            .insert_resource(EditorState::new())
+            .add_system(setup_editor.in_schedule(OnEnter(GameState::Editor)))
+            .add_system(
+                draw_level
+                    .after(paint_tiles)
+                    .after(editor_keys)
+                    .in_set(OnUpdate(GameState::Editor)),
+            )
+            .add_system(paint_tiles.in_set(OnUpdate(GameState::Editor)))
+            .add_system(palette_click.in_set(OnUpdate(GameState::Editor)))
+            .add_system(editor_keys.in_set(OnUpdate(GameState::Editor)))
+            .add_system(move_editor_camera.in_set(OnUpdate(GameState::Editor)))
+            .add_system(update_editor_text.in_set(OnUpdate(GameState::Editor)))
+            .add_system(leave_editor.in_schedule(OnExit(GameState::Editor)))
+            .add_system(despawn_everything.in_schedule(OnExit(GameState::Editor)));
+    }
+}
+
+// true while a level is being play-tested from the editor, so
+// escape and finishing the level come back to the editor
+#[derive(Resource)]
+pub struct EditorPlaytest(pub bool);
+
+#[derive(Resource)]
+struct EditorState {
+    // the tile that gets painted
+    selected: Tile,
+    // copies of the tiles from before each brush stroke
+    undo: Vec<Vec<Vec<u8>>>,
+    redo: Vec<Vec<Vec<u8>>>,
+    // the brush stroke being painted has already saved a copy for undo
+    stroke_saved: bool,
+    // the level needs to be spawned again
+    dirty: bool,
+    // a message for the user eg. "saved"
+    status: String,
+}
+
+impl EditorState {
+    fn new() -> Self {
+        EditorState {
+            selected: Tile::Wall,
+            undo: Vec::new(),
+            redo: Vec::new(),
+            stroke_saved: false,
+            dirty: true,
+            status: String::new(),
+        }
+    }
+}
+
+// everything the editor spawns for the level, so it can be despawned and redrawn
+#[derive(Component)]
+struct EditorTile;
+
+#[derive(Component)]
+struct EditorCamera;
+
+#[derive(Component)]
+struct EditorText;
+
+// one of the buttons in the palette
+#[derive(Component)]
+struct PaletteButton(Tile);
+
+// the colour used for each tile in the palette, the same as the macros use
+fn tile_colour(tile: Tile) -> Color {
+    match tile {
+        Tile::Empty => BACKGROUND_COLOUR,
+        Tile::Wall => Color::rgba(1.0, 1.0, 1.0, 1.0),
+        Tile::MovableWall => Color::rgba(0.0, 1.0, 1.0, 0.7),
+        Tile::Spawn => Color::BLUE,
+        Tile::KillerWall => Color::rgba(1.0, 0.0, 0., 1.0),
+        Tile::Goal => Color::rgba(0.0, 1.0, 0.0, 1.0),
+    }
+}
+
+fn setup_editor(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut maps: ResMut<Maps>,
+    current_level: Res<CurrentLevel>,
+    mut editor: ResMut<EditorState>,
+    mut rapier_config: ResMut<RapierConfiguration>,
+    mut playtest: ResMut<EditorPlaytest>,
+) {
+    playtest.0 = false;
+    commands.insert_resource(ClearColor(BACKGROUND_COLOUR));
+
+    // nothing should fall or move while editing
+    rapier_config.physics_pipeline_active = false;
+
+    // make an empty level if this one doesn't exist yet
+    let level = maps
+        .maps
+        .entry(current_level.level_number)
+        .or_insert_with(|| new_level(current_level.level_number));
+
+    // start with the camera in the middle of the level
+    let width = level.tiles.iter().map(|row| row.len()).max().unwrap_or(0);
+    let center = Vec2::new(width as f32, level.tiles.len() as f32) * MAP_SCALE / 2.0;
+    commands
+        .spawn(Camera2dBundle {
+            transform: Transform::from_translation(center.extend(999.0)),
+            ..Default::default()
+        })
+        .insert(EditorCamera);
+
+    editor.dirty = true;
+
+    // the help text at the top of the screen
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font: asset_server.load("fonts/Rubik-SemiBold.ttf"),
+                font_size: 24.0,
+                color: Color::BLACK,
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                top: Val::Px(10.0),
+                left: Val::Px(10.0),
+                ..default()
+            },
+            ..default()
+        }),
+        EditorText,
+    ));
+
+    // the palette down the left side
+    for (i, tile) in PALETTE.iter().enumerate() {
+        commands
+            .spawn(ButtonBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect {
+                        bottom: Val::Px(10.0 + i as f32 * 50.0),
+                        left: Val::Px(10.0),
+                        ..default()
+                    },
+                    size: Size::new(Val::Px(40.0), Val::Px(40.0)),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                background_color: tile_colour(*tile).into(),
+                ..default()
+            })
+            .insert(PaletteButton(*tile))
+            .with_children(|parent| {
+                // the number key for this tile
+                parent.spawn(TextBundle::from_section(
+                    i.to_string(),
+                    TextStyle {
+                        font: asset_server.load("fonts/Rubik-SemiBold.ttf"),
+                        font_size: 24.0,
+                        color: Color::BLACK,
+                    },
+                ));
+            });
+    }
+}
+
+fn leave_editor(mut rapier_config: ResMut<RapierConfiguration>) {
+    rapier_config.physics_pipeline_active = true;
+}
+
+// a small empty level with a floor, a spawn and a goal
+fn new_level(level_number: u8) -> Level {
+    let mut tiles = vec![vec![0; 10]; 6];
+    tiles[0] = vec![1; 10];
+    tiles[1][1] = Tile::Spawn.code();
+    tiles[1][8] = Tile::Goal.code();
+
+    Level {
+        name: format!("Level {level_number}"),
+        author: String::new(),
+        par_time: None,
+        tiles,
+    }
+}
+
+// despawns the level and spawns it again when it has changed
+fn draw_level(
+    mut commands: Commands,
+    mut editor: ResMut<EditorState>,
+    maps: Res<Maps>,
+    current_level: Res<CurrentLevel>,
+    game_textures: Res<GameTextures>,
+    tiles: Query<
+        Entity,
+        Or<(
+            With<EditorTile>,
+            With<Wall>,
+            With<KillerWall>,
+            With<MovableWall>,
+            With<Goal>,
+        )>,
+    >,
+) {
+    if !editor.dirty {
+        return;
+    }
+    editor.dirty = false;
+
+    for entity in tiles.iter() {
+        commands.entity(entity).despawn();
+    }
+
+    let Some(level) = maps.maps.get(&current_level.level_number) else {
+        return;
+    };
+
+    // a faint background so you can see how big the level is
+    let width = level.tiles.iter().map(|row| row.len()).max().unwrap_or(0) as f32;
+    let height = level.tiles.len() as f32;
+    commands
+        .spawn(SpriteBundle {
+            sprite: Sprite {
+                color: Color::rgba(0.0, 0.0, 0.0, 0.1),
+                custom_size: Some(Vec2::new(width, height) * MAP_SCALE),
+                ..default()
+            },
+            transform: Transform::from_xyz(
+                (width - 1.0) * MAP_SCALE / 2.0,
+                (height - 1.0) * MAP_SCALE / 2.0,
+                0.0,
+            ),
+            ..Default::default()
+        })
+        .insert(EditorTile);
+
+    // the same as platform_from_map_system but every spawn is drawn and
+    // the player is just a picture
+    for (y, array) in level.tiles.iter().enumerate() {
+        for (x, val) in array.iter().enumerate() {
+            let x = x as f32 * MAP_SCALE;
+            let y = y as f32 * MAP_SCALE;
+
+            match Tile::from_code(*val) {
+                Some(Tile::Wall) => {
+                    create_wall!(commands, x, y, Vec2::new(MAP_SCALE, MAP_SCALE))
+                }
+                Some(Tile::MovableWall) => {
+                    create_movable_wall!(
+                        commands,
+                        x,
+                        y,
+                        Vec2::new(MAP_SCALE, MAP_SCALE),
+                        current_level.level_number
+                    )
+                }
+                Some(Tile::Spawn) => {
+                    commands
+                        .spawn(SpriteBundle {
+                            texture: game_textures.player.clone(),
+                            sprite: Sprite {
+                                custom_size: Some(FELLA_SPRITE_SIZE),
+                                ..Default::default()
+                            },
+                            transform: Transform::from_xyz(x, y, 10.0),
+                            ..Default::default()
+                        })
+                        .insert(EditorTile);
+                }
+                Some(Tile::KillerWall) => {
+                    create_killer_wall!(commands, x, y, Vec2::new(MAP_SCALE, MAP_SCALE - 10.0))
+                }
+                Some(Tile::Goal) => {
+                    create_level_end!(commands, x, y, Vec2::new(MAP_SCALE, MAP_SCALE))
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+// gets the grid position under the mouse cursor
+fn cursor_tile(window: &Window, camera: &Transform) -> Option<(i32, i32)> {
+    let mut position = window.cursor_position()?;
+    position.x -= (window.width() / 2.0) - camera.translation.x;
+    position.y -= (window.height() / 2.0) - camera.translation.y;
+
+    // tiles are centered on multiples of the map scale
+    let x = (position.x / MAP_SCALE).round() as i32;
+    let y = (position.y / MAP_SCALE).round() as i32;
+    Some((x, y))
+}
+
+fn paint_tiles(
+    mouse: Res<Input<MouseButton>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    camera: Query<&Transform, With<EditorCamera>>,
+    palette: Query<&Interaction, With<PaletteButton>>,
+    mut editor: ResMut<EditorState>,
+    mut maps: ResMut<Maps>,
+    current_level: Res<CurrentLevel>,
+) {
+    // left click paints, right click rubs out
+    let tile = if mouse.pressed(MouseButton::Left) {
+        editor.selected
+    } else if mouse.pressed(MouseButton::Right) {
+        Tile::Empty
+    } else {
+        // the stroke is over, the next one saves a new copy
+        if editor.stroke_saved {
+            editor.stroke_saved = false;
+        }
+        return;
+    };
+    if mouse.just_pressed(MouseButton::Left) || mouse.just_pressed(MouseButton::Right) {
+        editor.stroke_saved = false;
+    }
+
+    // don't paint underneath the palette
+    if palette.iter().any(|i| *i != Interaction::None) {
+        return;
+    }
+
+    let (Ok(window), Ok(camera)) = (windows.get_single(), camera.get_single()) else {
+        return;
+    };
+    let Some((x, y)) = cursor_tile(window, camera) else {
+        return;
+    };
+    // the grid can grow up and to the right but not below 0
+    if x < 0 || y < 0 {
+        return;
+    }
+    let (x, y) = (x as usize, y as usize);
+
+    let Some(level) = maps.maps.get_mut(&current_level.level_number) else {
+        return;
+    };
+
+    let outside = y >= level.tiles.len() || x >= level.tiles[y].len();
+    // rubbing out outside of the grid does nothing
+    if outside && tile == Tile::Empty {
+        return;
+    }
+    // neither does painting a tile that is already there
+    if !outside && level.tiles[y][x] == tile.code() {
+        return;
+    }
+
+    // save a copy before the first tile each brush stroke changes so it can be undone
+    if !editor.stroke_saved {
+        editor.undo.push(level.tiles.clone());
+        editor.redo.clear();
+        editor.stroke_saved = true;
+    }
+
+    if outside {
+        // make the grid bigger so the tile fits
+        let width = level
+            .tiles
+            .iter()
+            .map(|row| row.len())
+            .max()
+            .unwrap_or(0)
+            .max(x + 1);
+        while level.tiles.len() <= y {
+            level.tiles.push(Vec::new());
+        }
+        for row in level.tiles.iter_mut() {
+            row.resize(width, Tile::Empty.code());
+        }
+    }
+
+    level.tiles[y][x] = tile.code();
+    editor.dirty = true;
+}
+
+fn palette_click(
+    buttons: Query<(&Interaction, &PaletteButton), Changed<Interaction>>,
+    mut editor: ResMut<EditorState>,
+) {
+    for (interaction, button) in buttons.iter() {
+        if *interaction == Interaction::Clicked {
+            editor.selected = button.0;
+        }
+    }
+}
+
+fn editor_keys(
+    keys: Res<Input<KeyCode>>,
+    mut editor: ResMut<EditorState>,
+    mut maps: ResMut<Maps>,
+    mut current_level: ResMut<CurrentLevel>,
+    mut game_state: ResMut<NextState<GameState>>,
+    mut playtest: ResMut<EditorPlaytest>,
+) {
+    let control = keys.any_pressed([KeyCode::LControl, KeyCode::RControl]);
+
+    // the number keys pick a tile
+    let numbers = [
+        KeyCode::Key0,
+        KeyCode::Key1,
+        KeyCode::Key2,
+        KeyCode::Key3,
+        KeyCode::Key4,
+        KeyCode::Key5,
+    ];
+    for (key, tile) in numbers.iter().zip(PALETTE) {
+        if keys.just_pressed(*key) {
+            editor.selected = tile;
+        }
+    }
+
+    // page up and down change which level is being edited
+    if keys.just_pressed(KeyCode::PageUp) || keys.just_pressed(KeyCode::PageDown) {
+        if keys.just_pressed(KeyCode::PageUp) {
+            // you can go one past the last level to make a new one
+            if maps.maps.contains_key(&current_level.level_number) {
+                current_level.level_number += 1;
+            }
+        } else if current_level.level_number > 1 {
+            current_level.level_number -= 1;
+        }
+        maps.maps
+            .entry(current_level.level_number)
+            .or_insert_with(|| new_level(current_level.level_number));
+
+        // history is per level
+        editor.undo.clear();
+        editor.redo.clear();
+        editor.dirty = true;
+        editor.status = String::new();
+    }
+
+    let Some(level) = maps.maps.get_mut(&current_level.level_number) else {
+        return;
+    };
+
+    if control && keys.just_pressed(KeyCode::Z) {
+        // undo
+        if let Some(tiles) = editor.undo.pop() {
+            let current = std::mem::replace(&mut level.tiles, tiles);
+            editor.redo.push(current);
+            editor.dirty = true;
+        }
+    } else if control && keys.just_pressed(KeyCode::Y) {
+        // redo
+        if let Some(tiles) = editor.redo.pop() {
+            let current = std::mem::replace(&mut level.tiles, tiles);
+            editor.undo.push(current);
+            editor.dirty = true;
+        }
+    } else if control && keys.just_pressed(KeyCode::S) {
+        // save it in the singleplayer levels folder
+        let path = level_directory(current_level.level_number, &HostClient::Play);
+        editor.status = match save_level(level, &path) {
+            Ok(()) => format!("saved to {path}.ron"),
+            Err(e) => format!("could not save: {e}"),
+        };
+    } else if keys.just_pressed(KeyCode::P) {
+        // play-test the level as it is now, as long as it can be played
+        match validate_level(level) {
+            Ok(()) => {
+                playtest.0 = true;
+                game_state.set(GameState::Gameplay);
+            }
+            Err(problems) => editor.status = LevelError::Invalid(problems).to_string(),
+        }
+    }
+}
+
+fn move_editor_camera(
+    keys: Res<Input<KeyCode>>,
+    time: Res<Time>,
+    mut camera: Query<&mut Transform, With<EditorCamera>>,
+) {
+    let mut direction = Vec2::ZERO;
+    if keys.pressed(KeyCode::Left) {
+        direction.x -= 1.0;
+    }
+    if keys.pressed(KeyCode::Right) {
+        direction.x += 1.0;
+    }
+    if keys.pressed(KeyCode::Up) {
+        direction.y += 1.0;
+    }
+    if keys.pressed(KeyCode::Down) {
+        direction.y -= 1.0;
+    }
+
+    for mut transform in camera.iter_mut() {
+        transform.translation +=
+            (direction * EDITOR_CAMERA_SPEED * time.delta_seconds()).extend(0.0);
+    }
+}
+
+fn update_editor_text(
+    editor: Res<EditorState>,
+    menu_message: Res<MenuMessage>,
+    maps: Res<Maps>,
+    current_level: Res<CurrentLevel>,
+    mut text: Query<&mut Text, With<EditorText>>,
+) {
+    let name = maps
+        .maps
+        .get(&current_level.level_number)
+        .map(|level| level.name.clone())
+        .unwrap_or_default();
+
+    for mut text in &mut text {
+        text.sections[0].value = format!(
+            "Level {} ({})   painting: {:?}\n\
+             click paint, right click rub out, 0-5 pick a tile, arrows move\n\
+             ctrl+z undo, ctrl+y redo, ctrl+s save, p play-test, page up/down change level\n{}\n{}",
+            current_level.level_number, name, editor.selected, editor.status, menu_message.0
+        );
+    }
+}
@@ -0,0 +1,213 @@
+use std::{fs, path::PathBuf};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::config_directory,
+    editor::EditorPlaytest,
+    level::level_hash,
+    main_menu::HostClient,
+    messages::{decode, Facing},
+    platform::Maps,
+    player::Player,
+    race::format_time,
+    startup_plugin::GameTextures,
+    toast::Toasts,
+    CurrentLevel, GameState, MultiplayerSetting, FELLA_SPRITE_SIZE,
+};
+
+// the best runs are saved in this folder in the config folder
+const GHOST_FOLDER: &str = "ghosts";
+const GHOST_EXTENSION: &str = "ghost";
+// bumped when the file changes so old ghosts are ignored instead of misread
+const GHOST_VERSION: u16 = 1;
+// see-through so it doesn't get mistaken for the player
+const GHOST_COLOUR: Color = Color::rgba(1.0, 1.0, 1.0, 0.35);
+
+pub struct GhostPlugin;
+
+impl Plugin for GhostPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Attempt::default())
+            .add_system(start_attempt.in_schedule(OnEnter(GameState::Gameplay)))
+            .add_system(record_attempt.in_set(OnUpdate(GameState::Gameplay)))
+            .add_system(
+                move_ghost
+                    .after(record_attempt)
+                    .in_set(OnUpdate(GameState::Gameplay)),
+            )
+            // touching the goal always goes to the next level screen, even on the last level
+            .add_system(save_best_run.in_schedule(OnEnter(GameState::NextLevel)));
+    }
+}
+
+// where the player was at a point in the attempt
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct GhostFrame {
+    // seconds since the level was spawned
+    time: f32,
+    position: Vec2,
+    facing: Facing,
+}
+
+// the best run of a level. the file is named after the level's hash
+// so changing the map leaves its old ghost behind
+#[derive(Serialize, Deserialize)]
+struct GhostFile {
+    version: u16,
+    frames: Vec<GhostFrame>,
+}
+
+// the attempt at the level being played
+#[derive(Resource, Default)]
+struct Attempt {
+    // the level and its hash, None if this attempt isn't recorded
+    level: Option<(u8, u64)>,
+    time: f32,
+    frames: Vec<GhostFrame>,
+}
+
+// the best run, played back next to the player
+#[derive(Component)]
+struct Ghost {
+    frames: Vec<GhostFrame>,
+}
+
+// every attempt starts a new recording, and brings out the ghost if
+// the level has been finished before
+fn start_attempt(
+    mut commands: Commands,
+    mut attempt: ResMut<Attempt>,
+    setting: Res<MultiplayerSetting>,
+    playtest: Res<EditorPlaytest>,
+    current_level: Res<CurrentLevel>,
+    maps: Res<Maps>,
+    game_textures: Res<GameTextures>,
+) {
+    *attempt = Attempt::default();
+
+    // ghosts are only for single player, and play-tests change the map all the time
+    if !matches!(setting.0, HostClient::Play) || playtest.0 {
+        return;
+    }
+    let Some(level) = maps.maps.get(&current_level.level_number) else {
+        return;
+    };
+    let hash = level_hash(level);
+    attempt.level = Some((current_level.level_number, hash));
+
+    let Some(best) = load_ghost(hash) else {
+        return;
+    };
+    commands.spawn((
+        SpriteBundle {
+            texture: game_textures.player.clone(),
+            sprite: Sprite {
+                custom_size: Some(FELLA_SPRITE_SIZE),
+                color: GHOST_COLOUR,
+                ..default()
+            },
+            // behind the player
+            transform: Transform::from_translation(best[0].position.extend(9.0)),
+            ..default()
+        },
+        Ghost { frames: best },
+    ));
+}
+
+fn record_attempt(
+    time: Res<Time>,
+    mut attempt: ResMut<Attempt>,
+    player: Query<(&Transform, &Player)>,
+) {
+    if attempt.level.is_none() {
+        return;
+    }
+    let Ok((transform, player)) = player.get_single() else {
+        return;
+    };
+
+    attempt.time += time.delta_seconds();
+    let frame = GhostFrame {
+        time: attempt.time,
+        position: transform.translation.truncate(),
+        facing: player.facing,
+    };
+    attempt.frames.push(frame);
+}
+
+// the ghost is where the best run was at the same time into the level
+fn move_ghost(attempt: Res<Attempt>, mut ghosts: Query<(&Ghost, &mut Transform, &mut Sprite)>) {
+    for (ghost, mut transform, mut sprite) in ghosts.iter_mut() {
+        let frames = &ghost.frames;
+        let next = frames.partition_point(|frame| frame.time <= attempt.time);
+
+        // it waits at the goal once it has finished
+        let (position, facing) = match (next.checked_sub(1), frames.get(next)) {
+            (Some(last), Some(next)) => {
+                let last = frames[last];
+                let t = (attempt.time - last.time) / (next.time - last.time);
+                (last.position.lerp(next.position, t), last.facing)
+            }
+            (Some(last), None) => (frames[last].position, frames[last].facing),
+            (None, Some(next)) => (next.position, next.facing),
+            (None, None) => continue,
+        };
+        transform.translation.x = position.x;
+        transform.translation.y = position.y;
+        sprite.flip_x = facing == Facing::Left;
+    }
+}
+
+// keeps the attempt if it was faster than the ghost
+fn save_best_run(attempt: Res<Attempt>, mut toasts: ResMut<Toasts>) {
+    let Some((number, hash)) = attempt.level else {
+        return;
+    };
+    if attempt.frames.is_empty() {
+        return;
+    }
+
+    let best = load_ghost(hash).and_then(|frames| frames.last().map(|frame| frame.time));
+    if best.is_some_and(|best| best <= attempt.time) {
+        return;
+    }
+
+    match save_ghost(hash, &attempt.frames) {
+        Ok(()) if best.is_some() => toasts.push(format!(
+            "new best on level {number}: {}",
+            format_time(attempt.time)
+        )),
+        Ok(()) => (),
+        Err(e) => toasts.push(e),
+    }
+}
+
+fn ghost_path(hash: u64) -> PathBuf {
+    config_directory()
+        .join(GHOST_FOLDER)
+        .join(format!("{hash:016x}.{GHOST_EXTENSION}"))
+}
+
+// the frames of the best run of a level, if it has been finished before
+fn load_ghost(hash: u64) -> Option<Vec<GhostFrame>> {
+    let bytes = fs::read(ghost_path(hash)).ok()?;
+    let ghost = decode::<GhostFile>(&bytes)?;
+    if ghost.version != GHOST_VERSION || ghost.frames.is_empty() {
+        return None;
+    }
+    Some(ghost.frames)
+}
+
+fn save_ghost(hash: u64, frames: &[GhostFrame]) -> Result<(), String> {
+    let path = ghost_path(hash);
+    let ghost = GhostFile {
+        version: GHOST_VERSION,
+        frames: frames.to_vec(),
+    };
+
+    fs::create_dir_all(config_directory().join(GHOST_FOLDER))
+        .and_then(|_| fs::write(&path, bincode::serialize(&ghost).unwrap()))
+        .map_err(|e| format!("could not save the ghost to {}: {e}", path.display()))
+}
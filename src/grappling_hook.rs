@@ -1,8 +1,11 @@
 use bevy::{prelude::*, sprite::collide_aabb::collide, window::PrimaryWindow};
 
 use crate::{
+    input::{Action, Actions},
+    messages::HookState,
     platform::Wall,
     player::Player,
+    run_if::run_if_not_waiting_for_race,
     startup_plugin::{GameTextures, PlayerCamera},
     GameState, HOOK_SPEED, HOOK_SPRITE_SIZE,
 };
@@ -11,15 +14,19 @@ pub struct GrapplePlugin;
 
 impl Plugin for GrapplePlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(send_out_hook.in_set(OnUpdate(GameState::Gameplay)))
-            .add_system(
-                hook_sensor
-                    // this system runs after the hook movement system, not in paralel
-                    .after(hook_movement)
-                    .in_set(OnUpdate(GameState::Gameplay)),
-            )
-            .add_system(hook_movement.in_set(OnUpdate(GameState::Gameplay)))
-            .add_system(delete_and_rotate_hooks.in_set(OnUpdate(GameState::Gameplay)));
+        app.add_system(
+            send_out_hook
+                .in_set(OnUpdate(GameState::Gameplay))
+                .run_if(run_if_not_waiting_for_race),
+        )
+        .add_system(
+            hook_sensor
+                // this system runs after the hook movement system, not in paralel
+                .after(hook_movement)
+                .in_set(OnUpdate(GameState::Gameplay)),
+        )
+        .add_system(hook_movement.in_set(OnUpdate(GameState::Gameplay)))
+        .add_system(delete_and_rotate_hooks.in_set(OnUpdate(GameState::Gameplay)));
     }
 }
 
@@ -27,7 +34,7 @@ impl Plugin for GrapplePlugin {
 pub struct MovingGrappleHook {
     // the component that describes a grappling
     // hook as it's moving
-    direction: Vec2,
+    pub direction: Vec2,
     size: Vec2,
     timer: Timer,
 }
@@ -35,9 +42,23 @@ pub struct MovingGrappleHook {
 #[derive(Component)]
 pub struct Hook;
 
+// what the player's hook is doing, from the hook if there is one out
+pub fn hook_state(hook: Option<(&Transform, Option<&MovingGrappleHook>)>) -> HookState {
+    match hook {
+        Some((hook, Some(moving))) => HookState::Flying {
+            position: hook.translation.truncate(),
+            direction: moving.direction,
+        },
+        Some((hook, None)) => HookState::Attached {
+            anchor: hook.translation.truncate(),
+        },
+        None => HookState::None,
+    }
+}
+
 // sends out a hitbox to act as the hook
 fn send_out_hook(
-    mouse: Res<Input<MouseButton>>,
+    actions: Res<Actions>,
     windows: Query<&Window, With<PrimaryWindow>>,
     mut commands: Commands,
     camera: Query<&Transform, With<PlayerCamera>>,
@@ -45,54 +66,60 @@ fn send_out_hook(
     game_textures: Res<GameTextures>,
     hooks: Query<&Hook>,
 ) {
-    // when you rightclick
-    if mouse.just_pressed(MouseButton::Right) && hooks.is_empty() {
-        
-        let window = windows.get_single().unwrap();
-        let camera = camera.single();
-
-        if let Some(mut position) = window.cursor_position() {
-            // calculate the cursor position
-            position.x -= (window.width() / 2.0) - camera.translation.x;
-            position.y -= (window.height() / 2.0) - camera.translation.y;
-
-            let player = player.single();
-
-            // vector from the player towards the cursor
-            let mut direction = position - player.translation.truncate();
-
-            // normalise the vector
-            direction /= direction.length();
-
-            // the angle that the hook makes against the player
-            let angle = Vec2::Y.angle_between(direction);
-
-            // spawn the hook
-            commands
-                .spawn(SpriteBundle {
-                    texture: game_textures.hook.clone(),
-                    sprite: Sprite {
-                        custom_size: Some(HOOK_SPRITE_SIZE),
-                        ..Default::default()
-                    },
-                    transform: Transform {
-                        // spawn the hook 20 pixels away from the center of the player
-                        // .extend() adds a z value
-                        translation: player.translation + (20.0 * direction).extend(11.0),
-                        // rotate it about the z axis so that it faces away from the player
-                        rotation: Quat::from_rotation_z(angle),
-                        ..Default::default()
-                    },
+    // when you rightclick (or pull the right trigger)
+    if actions.just_pressed(Action::FireHook) && hooks.is_empty() {
+        let player = player.single();
+
+        // aim with the right stick if it is pushed, otherwise at the cursor
+        let direction = match actions.aim {
+            Some(aim) => aim,
+            None => {
+                let window = windows.get_single().unwrap();
+                let camera = camera.single();
+
+                let Some(mut position) = window.cursor_position() else {
+                    return;
+                };
+                // calculate the cursor position
+                position.x -= (window.width() / 2.0) - camera.translation.x;
+                position.y -= (window.height() / 2.0) - camera.translation.y;
+
+                // vector from the player towards the cursor
+                let direction = position - player.translation.truncate();
+
+                // normalise the vector
+                direction / direction.length()
+            }
+        };
+
+        // the angle that the hook makes against the player
+        let angle = Vec2::Y.angle_between(direction);
+
+        // spawn the hook
+        commands
+            .spawn(SpriteBundle {
+                texture: game_textures.hook.clone(),
+                sprite: Sprite {
+                    custom_size: Some(HOOK_SPRITE_SIZE),
                     ..Default::default()
-                })
-                .insert(MovingGrappleHook {
-                    // add the moving grapple component
-                    direction,
-                    size: HOOK_SPRITE_SIZE,
-                    timer: Timer::from_seconds(0.7, TimerMode::Once),
-                })
-                .insert(Hook);
-        }
+                },
+                transform: Transform {
+                    // spawn the hook 20 pixels away from the center of the player
+                    // .extend() adds a z value
+                    translation: player.translation + (20.0 * direction).extend(11.0),
+                    // rotate it about the z axis so that it faces away from the player
+                    rotation: Quat::from_rotation_z(angle),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(MovingGrappleHook {
+                // add the moving grapple component
+                direction,
+                size: HOOK_SPRITE_SIZE,
+                timer: Timer::from_seconds(0.7, TimerMode::Once),
+            })
+            .insert(Hook);
     }
 }
 
@@ -149,12 +176,12 @@ fn delete_and_rotate_hooks(
     mut grappling_hook: Query<(Entity, &mut Transform), (Without<MovingGrappleHook>, With<Hook>)>,
     player: Query<&Transform, (With<Player>, Without<Hook>)>,
     mut commands: Commands,
-    keys: Res<Input<KeyCode>>,
+    actions: Res<Actions>,
 ) {
     for (hook, mut hook_t) in grappling_hook.iter_mut() {
 
-        // if they pressed space
-        if keys.just_pressed(KeyCode::Space) {
+        // if they pressed release (space)
+        if actions.just_pressed(Action::ReleaseHook) {
             // hook is deleted
             commands.entity(hook).despawn()
 
@@ -0,0 +1,134 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+use bevy::{app::ScheduleRunnerSettings, prelude::*, utils::HashMap};
+
+use crate::{
+    auth::load_or_create_key,
+    discovery::DiscoveryResponder,
+    hosting::lan_ip,
+    main_menu::{read_and_parse_files, HostClient},
+    messages::GameMode,
+    platform::Maps,
+    server::{bind_renet_server, MyServerPlugin, ServerLobby},
+    settings::load_settings,
+    MultiplayerSetting,
+};
+
+// how many times a second the dedicated server updates
+const SERVER_TICK_RATE: f64 = 60.0;
+
+// runs a dedicated server with no window or renderer so it can sit on a
+// linux box with no gpu and keep a lobby up.
+// usage: multiplayer_platformer --server [--race] [--secure] [bind address] [public address]
+pub fn run_headless_server(args: &[String]) {
+    // --race times everyone instead of letting them play at their own pace
+    let mode = if args.iter().any(|a| a == "--race") {
+        GameMode::Race
+    } else {
+        GameMode::FreeForAll
+    };
+    // --secure only lets in players with a token made with --issue-token
+    let secure = args.iter().any(|a| a == "--secure");
+
+    // the port and bind address in the settings file are the defaults
+    let settings = load_settings();
+    let port = settings.server_port;
+
+    // the addresses that come after --server
+    let mut args = args
+        .iter()
+        .skip_while(|a| a.as_str() != "--server")
+        .skip(1)
+        .filter(|a| !a.starts_with("--"));
+
+    // the address the socket listens on. defaults to every interface
+    let bind_addr = match args.next().map(|a| parse_address(a, port)) {
+        Some(Some(addr)) => addr,
+        Some(None) => return,
+        None => SocketAddr::new(settings.bind_address, port),
+    };
+
+    // the address clients type in to connect. if we are listening on every
+    // interface, guess the lan ip
+    let public_addr = match args.next().map(|a| parse_address(a, port)) {
+        Some(Some(addr)) => addr,
+        Some(None) => return,
+        None if bind_addr.ip().is_unspecified() => SocketAddr::new(lan_ip(), bind_addr.port()),
+        None => bind_addr,
+    };
+
+    // load the multiplayer maps the same way the host button does
+    let mut maps = Maps {
+        maps: HashMap::new(),
+    };
+    // the errors have already been printed, don't host broken levels
+    if read_and_parse_files(1, &mut maps, HostClient::Host).is_err() {
+        return;
+    }
+
+    let private_key = if secure {
+        match load_or_create_key() {
+            Ok(key) => Some(key),
+            Err(e) => {
+                println!("{e}");
+                return;
+            }
+        }
+    } else {
+        None
+    };
+
+    let server = match bind_renet_server(bind_addr, public_addr, private_key) {
+        Ok(server) => server,
+        Err(e) => {
+            println!("could not bind the server to {bind_addr}: {e}");
+            return;
+        }
+    };
+
+    println!("server listening on {bind_addr}, clients connect to {public_addr}");
+    if secure {
+        println!("secure: make each player a token with --issue-token <name> 24 {public_addr}");
+    }
+
+    let mut app = App::new();
+    app
+        // don't spin the cpu at 100%, tick at a fixed rate instead
+        .insert_resource(ScheduleRunnerSettings::run_loop(Duration::from_secs_f64(
+            1.0 / SERVER_TICK_RATE,
+        )))
+        .add_plugins(MinimalPlugins)
+        // tells the server systems to run
+        .insert_resource(MultiplayerSetting(HostClient::Host))
+        .insert_resource(maps)
+        .insert_resource(server)
+        .insert_resource(ServerLobby {
+            host: None,
+            started: false,
+            mode,
+        })
+        .add_plugin(MyServerPlugin);
+
+    // let players on the lan find the server. if the port is taken it still runs
+    if let Some(responder) = DiscoveryResponder::bind(public_addr) {
+        app.insert_resource(responder);
+    }
+
+    // runs forever
+    app.run();
+}
+
+// parses "ip:port" or just "ip" (uses the port from the settings)
+fn parse_address(address: &str, port: u16) -> Option<SocketAddr> {
+    if let Ok(addr) = address.parse::<SocketAddr>() {
+        Some(addr)
+    } else if let Ok(ip) = address.parse::<IpAddr>() {
+        Some(SocketAddr::new(ip, port))
+    } else {
+        println!("{address} is not a valid address, use ip:port or ip");
+        None
+    }
+}
@@ -0,0 +1,190 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{
+        mpsc::{self, Receiver, TryRecvError},
+        Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use bevy::{prelude::*, utils::HashMap};
+use local_ip_address::local_ip;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::load_or_create_key,
+    client::new_renet_client,
+    discovery::DiscoveryResponder,
+    main_menu::HostClient,
+    messages::GameMode,
+    server::{new_renet_server, DepartedClients, ServerClients, ServerLobby, ServerRace},
+    settings::Settings,
+    MultiplayerSetting,
+};
+
+// how long to wait for the public ip before hosting on the lan instead
+const PUBLIC_IP_TIMEOUT: Duration = Duration::from_secs(5);
+
+// where players can join the host from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum HostMode {
+    // players on the same network join with this machine's lan ip
+    #[default]
+    Lan,
+    // players join with the bind address in the settings, eg 127.0.0.1 to test on one machine
+    Address,
+    // players join over the internet with the public ip. it needs port forwarding
+    Public,
+}
+
+impl HostMode {
+    // the next one along, used by the arrow keys in the settings menu
+    pub fn next(self) -> Self {
+        match self {
+            HostMode::Lan => HostMode::Address,
+            HostMode::Address => HostMode::Public,
+            HostMode::Public => HostMode::Lan,
+        }
+    }
+
+    pub fn previous(self) -> Self {
+        self.next().next()
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HostMode::Lan => "LAN",
+            HostMode::Address => "Host address",
+            HostMode::Public => "Public (internet)",
+        }
+    }
+}
+
+// what the host's lobby shows about how to join
+#[derive(Resource)]
+pub struct HostInfo {
+    // the address other players type in
+    pub address: SocketAddr,
+    // eg why it is on the lan when public was picked, empty if there is nothing to say
+    pub note: String,
+}
+
+// this machine's ip on the local network, or loopback if it isn't on one
+pub fn lan_ip() -> IpAddr {
+    local_ip().unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST))
+}
+
+// the address players use for the lan and address modes, and a note if the
+// settings couldn't be used as they are
+pub fn local_host_ip(settings: &Settings) -> (IpAddr, String) {
+    // a server bound to one address can only be reached on that address
+    if !settings.bind_address.is_unspecified() {
+        return (settings.bind_address, String::new());
+    }
+
+    match settings.host_mode {
+        HostMode::Address => (
+            lan_ip(),
+            "no host address is set in the settings, hosting on the LAN".to_string(),
+        ),
+        HostMode::Lan | HostMode::Public => (lan_ip(), String::new()),
+    }
+}
+
+// looks up the public ip on another thread so the game doesn't freeze
+// (or crash) while it waits for the internet
+#[derive(Resource)]
+pub struct PublicIpLookup(Mutex<Receiver<Option<IpAddr>>>);
+
+impl PublicIpLookup {
+    pub fn start() -> Self {
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            let ip = match tokio::runtime::Runtime::new() {
+                Ok(rt) => rt.block_on(async {
+                    tokio::time::timeout(PUBLIC_IP_TIMEOUT, public_ip::addr())
+                        .await
+                        .ok()
+                        .flatten()
+                }),
+                Err(e) => {
+                    println!("could not start the public ip lookup: {e}");
+                    None
+                }
+            };
+            // nobody may be waiting any more, that's fine
+            let _ = sender.send(ip);
+        });
+
+        PublicIpLookup(Mutex::new(receiver))
+    }
+
+    // None while it is still looking, Some(None) if it couldn't find it
+    pub fn poll(&self) -> Option<Option<IpAddr>> {
+        match self.0.lock().unwrap().try_recv() {
+            Ok(ip) => Some(ip),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(None),
+        }
+    }
+}
+
+// starts the server and the host's own client. players join with public_ip.
+// the error is shown on the menu if the server can't be started
+pub fn start_hosting(
+    commands: &mut Commands,
+    settings: &Settings,
+    public_ip: IpAddr,
+    mut note: String,
+) -> Result<(), String> {
+    // a secure server only lets in players with a token made with its key
+    let private_key = if settings.secure {
+        Some(load_or_create_key()?)
+    } else {
+        None
+    };
+
+    let server = new_renet_server(public_ip, settings, private_key).map_err(|e| {
+        format!(
+            "could not host on {}:{}: {e}",
+            settings.bind_address, settings.server_port
+        )
+    })?;
+    let client =
+        new_renet_client(public_ip, settings, private_key.as_ref()).map_err(|e| e.to_string())?;
+
+    let address = SocketAddr::new(public_ip, settings.server_port);
+    println!("hosting, players join with {address}");
+    if private_key.is_some() {
+        if !note.is_empty() {
+            note.push_str(", ");
+        }
+        note.push_str(&format!(
+            "secure: make each player a token with --issue-token <name> 24 {address}"
+        ));
+    }
+
+    // tells the systems that we are the host
+    commands.insert_resource(MultiplayerSetting(HostClient::Host));
+    // we are the one who decides when the game starts
+    commands.insert_resource(ServerLobby {
+        host: Some(client.client_id()),
+        started: false,
+        mode: GameMode::FreeForAll,
+    });
+    // forget the players and race of a game hosted before, the host has a new id now
+    commands.insert_resource(ServerClients(HashMap::new()));
+    commands.insert_resource(ServerRace::default());
+    // and the players who left it, a new player could have one of their ids
+    commands.insert_resource(DepartedClients::default());
+    // let players on the lan find the game
+    if let Some(responder) = DiscoveryResponder::bind(address) {
+        commands.insert_resource(responder);
+    }
+    commands.insert_resource(HostInfo { address, note });
+    commands.insert_resource(client);
+    commands.insert_resource(server);
+    Ok(())
+}
@@ -0,0 +1,269 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt, fs,
+};
+
+use bevy::{input::InputSystem, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{config_path, save_ron};
+
+// the file in the config folder the controls are saved in
+const BINDINGS_FILE: &str = "bindings.ron";
+
+// how far a stick has to be pushed before it counts
+const STICK_DEADZONE: f32 = 0.5;
+
+pub struct ActionPlugin;
+
+impl Plugin for ActionPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(load_bindings())
+            .init_resource::<Actions>()
+            // work out the actions straight after bevy reads the keyboard, mouse and gamepads
+            // so every system sees the same thing this frame
+            .add_system(
+                update_actions
+                    .in_base_set(CoreSet::PreUpdate)
+                    .after(InputSystem),
+            );
+    }
+}
+
+// everything the player can do. systems check these instead of keys so the
+// controls can be changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    Jump,
+    // pull the player towards the hook
+    Reel,
+    FireHook,
+    ReleaseHook,
+    Respawn,
+    // leave the level and go back to the menu
+    Pause,
+}
+
+impl Action {
+    // in the order they are shown on the controls screen
+    pub const ALL: [Action; 8] = [
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::Jump,
+        Action::Reel,
+        Action::FireHook,
+        Action::ReleaseHook,
+        Action::Respawn,
+        Action::Pause,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::MoveLeft => "Move left",
+            Action::MoveRight => "Move right",
+            Action::Jump => "Jump",
+            Action::Reel => "Reel in",
+            Action::FireHook => "Fire hook",
+            Action::ReleaseHook => "Release hook",
+            Action::Respawn => "Respawn",
+            Action::Pause => "Pause",
+        }
+    }
+}
+
+// a key or button that does an action
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    Gamepad(GamepadButtonType),
+}
+
+impl Binding {
+    pub fn is_gamepad(&self) -> bool {
+        matches!(self, Binding::Gamepad(_))
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Binding::Key(key) => write!(f, "{key:?}"),
+            Binding::Mouse(button) => write!(f, "Mouse {button:?}"),
+            Binding::Gamepad(button) => write!(f, "Pad {button:?}"),
+        }
+    }
+}
+
+// which keys and buttons do each action. an action can have more than one
+#[derive(Resource, Debug, Clone, Serialize, Deserialize)]
+pub struct Bindings(pub BTreeMap<Action, Vec<Binding>>);
+
+impl Default for Bindings {
+    fn default() -> Self {
+        use Binding::*;
+
+        let bindings = [
+            (
+                Action::MoveLeft,
+                vec![Key(KeyCode::A), Gamepad(GamepadButtonType::DPadLeft)],
+            ),
+            (
+                Action::MoveRight,
+                vec![Key(KeyCode::D), Gamepad(GamepadButtonType::DPadRight)],
+            ),
+            (
+                Action::Jump,
+                vec![Key(KeyCode::Space), Gamepad(GamepadButtonType::South)],
+            ),
+            (
+                Action::Reel,
+                vec![Key(KeyCode::W), Gamepad(GamepadButtonType::LeftTrigger2)],
+            ),
+            (
+                Action::FireHook,
+                vec![
+                    Mouse(MouseButton::Right),
+                    Gamepad(GamepadButtonType::RightTrigger2),
+                ],
+            ),
+            (
+                Action::ReleaseHook,
+                vec![Key(KeyCode::Space), Gamepad(GamepadButtonType::South)],
+            ),
+            (
+                Action::Respawn,
+                vec![Key(KeyCode::R), Gamepad(GamepadButtonType::West)],
+            ),
+            (
+                Action::Pause,
+                vec![Key(KeyCode::Escape), Gamepad(GamepadButtonType::Start)],
+            ),
+        ];
+        Bindings(bindings.into_iter().collect())
+    }
+}
+
+impl Bindings {
+    pub fn get(&self, action: Action) -> &[Binding] {
+        self.0.get(&action).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    // sets the keyboard and mouse binding of an action, or the gamepad binding if it is a
+    // gamepad button. the other kind is kept so a keyboard player doesn't break the gamepad
+    pub fn rebind(&mut self, action: Action, binding: Binding) {
+        let bindings = self.0.entry(action).or_default();
+        bindings.retain(|b| b.is_gamepad() != binding.is_gamepad());
+        bindings.insert(0, binding);
+    }
+
+    // writes the bindings to the config folder. it only prints if it fails,
+    // the controls still work until the game is closed
+    pub fn save(&self) {
+        if let Err(e) = save_ron(BINDINGS_FILE, self) {
+            println!("could not save the controls: {e}");
+        }
+    }
+}
+
+// reads the saved bindings, or the defaults if there aren't any or they are broken
+pub fn load_bindings() -> Bindings {
+    let path = config_path(BINDINGS_FILE);
+    let Ok(contents) = fs::read_to_string(&path) else {
+        return Bindings::default();
+    };
+
+    match ron::from_str::<Bindings>(&contents) {
+        Ok(mut bindings) => {
+            // actions added since the file was saved get their default controls
+            for (action, default) in Bindings::default().0 {
+                bindings.0.entry(action).or_insert(default);
+            }
+            bindings
+        }
+        Err(e) => {
+            println!(
+                "could not read {}: {e}, using the default controls",
+                path.display()
+            );
+            Bindings::default()
+        }
+    }
+}
+
+// the actions being done this frame
+#[derive(Resource, Default)]
+pub struct Actions {
+    pressed: HashSet<Action>,
+    just_pressed: HashSet<Action>,
+    // the direction the right stick is pushed, None if it isn't
+    pub aim: Option<Vec2>,
+    // set while the chat box is open so typing doesn't move the player
+    pub typing: bool,
+}
+
+impl Actions {
+    pub fn pressed(&self, action: Action) -> bool {
+        self.pressed.contains(&action)
+    }
+
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.just_pressed.contains(&action)
+    }
+}
+
+fn update_actions(
+    bindings: Res<Bindings>,
+    mut actions: ResMut<Actions>,
+    keys: Res<Input<KeyCode>>,
+    mouse: Res<Input<MouseButton>>,
+    gamepads: Res<Gamepads>,
+    buttons: Res<Input<GamepadButton>>,
+    axes: Res<Axis<GamepadAxis>>,
+) {
+    let mut pressed = HashSet::new();
+    let typing = actions.typing;
+
+    for (action, action_bindings) in bindings.0.iter() {
+        let down = action_bindings.iter().any(|binding| match binding {
+            Binding::Key(key) => !typing && keys.pressed(*key),
+            Binding::Mouse(button) => mouse.pressed(*button),
+            // any gamepad that is plugged in
+            Binding::Gamepad(button) => gamepads
+                .iter()
+                .any(|gamepad| buttons.pressed(GamepadButton::new(gamepad, *button))),
+        });
+        if down {
+            pressed.insert(*action);
+        }
+    }
+
+    // the left stick moves and the right stick aims the hook
+    actions.aim = None;
+    for gamepad in gamepads.iter() {
+        let stick = |x, y| {
+            Vec2::new(
+                axes.get(GamepadAxis::new(gamepad, x)).unwrap_or(0.0),
+                axes.get(GamepadAxis::new(gamepad, y)).unwrap_or(0.0),
+            )
+        };
+
+        let left = stick(GamepadAxisType::LeftStickX, GamepadAxisType::LeftStickY);
+        if left.x < -STICK_DEADZONE {
+            pressed.insert(Action::MoveLeft);
+        } else if left.x > STICK_DEADZONE {
+            pressed.insert(Action::MoveRight);
+        }
+
+        let right = stick(GamepadAxisType::RightStickX, GamepadAxisType::RightStickY);
+        if right.length() > STICK_DEADZONE {
+            actions.aim = Some(right.normalize());
+        }
+    }
+
+    // an action is only just pressed on the first frame, however it was pressed
+    actions.just_pressed = pressed.difference(&actions.pressed).copied().collect();
+    actions.pressed = pressed;
+}
@@ -0,0 +1,205 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::messages::{Facing, HookState};
+
+// the most snapshots kept for each remote player. at 60 a second this is
+// about half a second, much more than the interpolation delay
+const MAX_SNAPSHOTS: usize = 32;
+
+// if a snapshot is this many seconds later than the clock offset expects, the
+// sender's clock must have jumped (eg they restarted) so the buffer starts again
+const CLOCK_RESET_THRESHOLD: f64 = 1.0;
+
+// how remote players are smoothed out. it is part of the settings file so it can
+// be tuned for a bad connection
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InterpolationSettings {
+    // how far in the past (seconds) remote players are drawn. a bigger delay
+    // hides more jitter and packet loss but makes other players lag behind
+    pub delay: f64,
+    // how long (seconds) to keep moving a player past the last snapshot when
+    // packets stop arriving, after that they stand still
+    pub max_extrapolation: f64,
+}
+
+impl Default for InterpolationSettings {
+    fn default() -> Self {
+        InterpolationSettings {
+            delay: 0.1,
+            max_extrapolation: 0.25,
+        }
+    }
+}
+
+// where a player was and what they were doing at a time on the sender's clock
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Snapshot {
+    pub time: f64,
+    pub position: Vec3,
+    pub velocity: Vec2,
+    pub facing: Facing,
+    pub hook: HookState,
+}
+
+// the recent positions of one remote player. it is plain data with no
+// systems or rendering so it can be used on its own
+#[derive(Debug, Default, Clone)]
+pub struct SnapshotBuffer {
+    // oldest first
+    snapshots: VecDeque<Snapshot>,
+    // our clock minus the sender's clock for the snapshot that arrived the
+    // quickest. adding it to a sender time gives the local time it arrived
+    clock_offset: Option<f64>,
+}
+
+impl SnapshotBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // adds a snapshot that arrived at local_time. old or repeated
+    // snapshots are dropped because packets can arrive out of order
+    pub fn push(&mut self, snapshot: Snapshot, local_time: f64) {
+        let offset = local_time - snapshot.time;
+
+        match self.clock_offset {
+            Some(current) if offset > current + CLOCK_RESET_THRESHOLD => {
+                self.snapshots.clear();
+                self.clock_offset = Some(offset);
+            }
+            // the least delayed packet is the best guess of the clock difference
+            Some(current) => self.clock_offset = Some(current.min(offset)),
+            None => self.clock_offset = Some(offset),
+        }
+
+        if let Some(last) = self.snapshots.back() {
+            if snapshot.time <= last.time {
+                return;
+            }
+        }
+
+        self.snapshots.push_back(snapshot);
+        while self.snapshots.len() > MAX_SNAPSHOTS {
+            self.snapshots.pop_front();
+        }
+    }
+
+    // the time on the sender's clock that is drawn at local_time
+    fn render_time(&self, local_time: f64, settings: &InterpolationSettings) -> Option<f64> {
+        Some(local_time - self.clock_offset? - settings.delay)
+    }
+
+    // where to draw the player at local_time. None if nothing has arrived yet
+    pub fn sample(&self, local_time: f64, settings: &InterpolationSettings) -> Option<Vec3> {
+        let render_time = self.render_time(local_time, settings)?;
+        let first = self.snapshots.front()?;
+        let last = self.snapshots.back()?;
+
+        if render_time <= first.time {
+            return Some(first.position);
+        }
+
+        if render_time >= last.time {
+            return Some(self.extrapolate(render_time, settings));
+        }
+
+        // find the two snapshots either side and blend between them
+        let next = self
+            .snapshots
+            .iter()
+            .position(|s| s.time >= render_time)
+            .unwrap_or(self.snapshots.len() - 1);
+        let a = self.snapshots[next - 1];
+        let b = self.snapshots[next];
+        let t = ((render_time - a.time) / (b.time - a.time)) as f32;
+        Some(a.position.lerp(b.position, t))
+    }
+
+    // the newest snapshot that is not in the future at local_time. things like the
+    // hook and facing can't be blended so they change when the snapshot is reached
+    pub fn state_at(&self, local_time: f64, settings: &InterpolationSettings) -> Option<&Snapshot> {
+        let render_time = self.render_time(local_time, settings)?;
+        self.snapshots
+            .iter()
+            .rev()
+            .find(|s| s.time <= render_time)
+            .or_else(|| self.snapshots.front())
+    }
+
+    // keeps the player moving at the speed they were going for a little while
+    fn extrapolate(&self, render_time: f64, settings: &InterpolationSettings) -> Vec3 {
+        let last = self.snapshots[self.snapshots.len() - 1];
+        let ahead = (render_time - last.time).min(settings.max_extrapolation) as f32;
+        last.position + (last.velocity * ahead).extend(0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(time: f64, x: f32, velocity: f32) -> Snapshot {
+        Snapshot {
+            time,
+            position: Vec3::new(x, 0.0, 10.0),
+            velocity: Vec2::new(velocity, 0.0),
+            facing: Facing::Right,
+            hook: HookState::None,
+        }
+    }
+
+    // two snapshots sent at 0.0 and 0.1 that took exactly a second to arrive
+    fn two_snapshots() -> SnapshotBuffer {
+        let mut buffer = SnapshotBuffer::new();
+        buffer.push(snapshot(0.0, 0.0, 100.0), 1.0);
+        buffer.push(snapshot(0.1, 10.0, 100.0), 1.1);
+        buffer
+    }
+
+    fn x_at(buffer: &SnapshotBuffer, local_time: f64) -> f32 {
+        let settings = InterpolationSettings::default();
+        buffer.sample(local_time, &settings).unwrap().x
+    }
+
+    #[test]
+    fn samples_between_two_snapshots() {
+        let buffer = two_snapshots();
+        // drawn 0.1s in the past, so halfway between the snapshots
+        assert!((x_at(&buffer, 1.15) - 5.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn stays_at_the_first_snapshot_before_it() {
+        let buffer = two_snapshots();
+        assert_eq!(x_at(&buffer, 1.0), 0.0);
+        assert_eq!(x_at(&buffer, 0.0), 0.0);
+    }
+
+    #[test]
+    fn extrapolates_no_further_than_the_limit() {
+        let buffer = two_snapshots();
+        // 0.05s past the last snapshot at 100 a second
+        assert!((x_at(&buffer, 1.25) - 15.0).abs() < 0.01);
+        // long after, it stops max_extrapolation past it
+        assert!((x_at(&buffer, 5.0) - 35.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn clock_offset_converges_on_the_quickest_packet() {
+        let mut buffer = SnapshotBuffer::new();
+        for (i, delay) in [0.05, 0.02, 0.08, 0.0, 0.03].into_iter().enumerate() {
+            let time = i as f64 * 0.1;
+            buffer.push(snapshot(time, 0.0, 0.0), time + 1.0 + delay);
+        }
+        assert!((buffer.clock_offset.unwrap() - 1.0).abs() < 1e-9);
+
+        // a sender clock that jumps back starts the buffer again
+        buffer.push(snapshot(0.0, 0.0, 0.0), 10.0);
+        assert!((buffer.clock_offset.unwrap() - 10.0).abs() < 1e-9);
+        assert_eq!(buffer.snapshots.len(), 1);
+    }
+}
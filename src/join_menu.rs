@@ -1,24 +1,49 @@
 use std::{
-    net::{SocketAddr, UdpSocket},
+    fmt,
+    net::{IpAddr, SocketAddr, ToSocketAddrs, UdpSocket},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Receiver, TryRecvError},
+        Mutex,
+    },
+    thread,
     time::{Duration, SystemTime},
 };
 
 use bevy::prelude::*;
 
 use crate::{
-    client::PROTOCOL_ID,
+    auth::{saved_tokens, TokenFile},
+    client::{client_bind_address, send_hello, CLIENT_PORT_TRIES},
+    discovery::{search_for_servers, LanDiscovery},
     main_menu::{HostClient, Menu},
-    server::{CLIENT_PORT, SERVER_PORT},
+    messages::{name_to_user_data, MAX_NAME_LENGTH, PROTOCOL_ID, PROTOCOL_VERSION},
+    settings::Settings,
     startup_plugin::despawn_everything,
     GameState, MultiplayerSetting, BACKGROUND_COLOUR,
 };
 
-use bevy_renet::renet::{ClientAuthentication, RenetClient, RenetConnectionConfig};
+use bevy_renet::renet::{ClientAuthentication, ConnectToken, RenetClient, RenetConnectionConfig};
+
+// the longest address that can be typed, a hostname can be up to 253 characters
+const MAX_ADDRESS_LENGTH: usize = 260;
 
 pub enum BindError {
+    // there was no free port to connect from
     Client,
-    Server,
-    Format,
+    // the address couldn't be read or looked up
+    Address(String),
+    // the token file couldn't be used
+    Token(String),
+}
+
+impl fmt::Display for BindError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BindError::Client => write!(f, "could not find a free port to connect from"),
+            BindError::Address(e) | BindError::Token(e) => write!(f, "{e}"),
+        }
+    }
 }
 
 pub struct JoinMenuPlugin;
@@ -26,32 +51,110 @@ pub struct JoinMenuPlugin;
 impl Plugin for JoinMenuPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
         app.insert_resource(IPString(String::new()))
+            .insert_resource(JoinField::Ip)
             .insert_resource(BarTimer::new())
+            .init_resource::<JoinError>()
             .add_system(setup_join_menu.in_schedule(OnEnter(GameState::JoinMenu)))
             .add_system(join_input_ip.in_set(OnUpdate(GameState::JoinMenu)))
             .add_system(update_text.in_set(OnUpdate(GameState::JoinMenu)))
             .add_system(despawn_everything.in_schedule(OnExit(GameState::JoinMenu)))
-            .add_system(text_input.in_set(OnUpdate(GameState::JoinMenu)));
+            .add_system(stop_searching.in_schedule(OnExit(GameState::JoinMenu)))
+            .add_system(finish_join.in_set(OnUpdate(GameState::JoinMenu)))
+            .add_system(cancel_join.in_schedule(OnExit(GameState::JoinMenu)))
+            .add_system(text_input.in_set(OnUpdate(GameState::JoinMenu)))
+            .add_system(search_for_servers.in_set(OnUpdate(GameState::JoinMenu)))
+            .add_system(
+                update_server_list
+                    .after(search_for_servers)
+                    .in_set(OnUpdate(GameState::JoinMenu)),
+            )
+            .add_system(click_server.in_set(OnUpdate(GameState::JoinMenu)));
     }
 }
 
 #[derive(Resource)]
 pub struct IPString(String);
 
+// which box is being typed in, tab switches between them
+#[derive(Resource, PartialEq)]
+enum JoinField {
+    Ip,
+    Name,
+}
+
+// why the last join didn't work, shown under the boxes
+#[derive(Resource, Default)]
+struct JoinError(String);
+
+// the recent servers and the ones found on the lan
+#[derive(Component)]
+struct ServerList;
+
+// what joining a server needs
+#[derive(Clone)]
+pub enum JoinTarget {
+    // looked up again when it is clicked in case a hostname has moved
+    Address(String),
+    // a token file for a secure server, it has the address in it
+    Token(PathBuf),
+}
+
+// a server that can be clicked to join it
+#[derive(Component)]
+struct ServerButton(JoinTarget);
+
+// the server we last joined, it is used to reconnect and retry
+#[derive(Resource)]
+pub struct LastServer {
+    pub target: JoinTarget,
+    // our client id on that server. it stays the same when we reconnect so
+    // the server knows we are the same player
+    pub session: u64,
+}
+
 fn setup_join_menu(mut commands: Commands, asset_server: Res<AssetServer>) {
     // setup the text and camera and background
     commands.insert_resource(ClearColor(BACKGROUND_COLOUR));
     commands.spawn(Camera2dBundle::default());
+    commands.insert_resource(JoinError::default());
 
+    // look for games on the network while the menu is open
+    commands.insert_resource(LanDiscovery::start());
     commands.spawn((
-        TextBundle::from_section(
-            "Server IP: ",
-            TextStyle {
-                font: asset_server.load("fonts/Rubik-SemiBold.ttf"),
-                font_size: 60.0,
-                color: Color::BLACK,
+        NodeBundle {
+            style: Style {
+                flex_direction: FlexDirection::Column,
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(380.0),
+                    left: Val::Px(10.0),
+                    ..default()
+                },
+                ..default()
             },
-        ),
+            ..default()
+        },
+        ServerList,
+    ));
+
+    let style = TextStyle {
+        font: asset_server.load("fonts/Rubik-SemiBold.ttf"),
+        font_size: 60.0,
+        color: Color::BLACK,
+    };
+    // the boxes, then the error in red
+    commands.spawn((
+        TextBundle::from_sections([
+            TextSection::new("Server address: ", style.clone()),
+            TextSection::new(
+                "",
+                TextStyle {
+                    font_size: 40.0,
+                    color: Color::MAROON,
+                    ..style
+                },
+            ),
+        ]),
         Menu,
     ));
 }
@@ -83,6 +186,9 @@ impl BarTimer {
 
 fn update_text(
     ip_string: Res<IPString>,
+    settings: Res<Settings>,
+    field: Res<JoinField>,
+    error: Res<JoinError>,
     mut text: Query<&mut Text, With<Menu>>,
     mut timer: ResMut<BarTimer>,
     time: Res<Time>,
@@ -92,41 +198,56 @@ fn update_text(
         // tick the bar timer
         let b = timer.tick(dt);
         let mut a = ip_string.0.clone();
+        let mut n = settings.player_name.clone();
         if b {
-            // if b is true, put a bar at the end.
+            // if b is true, put a bar at the end of the box being typed in.
             // otherwise; don't.
-            a.push('|')
+            match *field {
+                JoinField::Ip => a.push('|'),
+                JoinField::Name => n.push('|'),
+            }
         }
         // update the text to show what you have typed already.
-        text.sections[0].value = format!("Server IP: {}", a);
+        text.sections[0].value = format!("Server address: {}\nName: {}\n\nTab: switch box\n", a, n);
+        if text.sections[1].value != error.0 {
+            text.sections[1].value = error.0.clone();
+        }
     }
 }
 
-fn text_input(mut char_evr: EventReader<ReceivedCharacter>, mut ip_string: ResMut<IPString>) {
+fn text_input(
+    mut char_evr: EventReader<ReceivedCharacter>,
+    mut ip_string: ResMut<IPString>,
+    mut settings: ResMut<Settings>,
+    mut field: ResMut<JoinField>,
+) {
     // takes input from the keyboard
     for ev in char_evr.iter() {
         let char = ev.char;
 
-        if char == '\x08' {
+        if char == '\t' {
+            // tab switches to the other box
+            *field = match *field {
+                JoinField::Ip => JoinField::Name,
+                JoinField::Name => JoinField::Ip,
+            };
+        } else if *field == JoinField::Name {
+            let name = &mut settings.player_name;
+            if char == '\x08' {
+                name.pop();
+            } else if !char.is_control() && name.chars().count() < MAX_NAME_LENGTH {
+                name.push(char);
+            }
+        } else if char == '\x08' {
             // if it's a backspace
             ip_string.0.pop();
             // pop the previous character
-        } else if char.is_numeric() {
-            // if it's a number
+        } else if !char.is_control()
+            && !char.is_whitespace()
+            && ip_string.0.len() < MAX_ADDRESS_LENGTH
+        {
+            // an ip, hostname or either with a port, it is checked when enter is pressed
             ip_string.0.push(char)
-            // add the character to the end
-        } else if char == '.' {
-            // if a . was inputted
-            if let Some(a) = ip_string.0.pop() {
-                // get the last characyter
-                ip_string.0.push(a);
-                // if the last character was a number
-                if a.is_numeric() {
-                    // push the .
-                    ip_string.0.push(char)
-                }
-                // if the last character was not a number don't push the .
-            }
         }
     }
 }
@@ -134,8 +255,10 @@ fn text_input(mut char_evr: EventReader<ReceivedCharacter>, mut ip_string: ResMu
 fn join_input_ip(
     mut commands: Commands,
 
-    // the user's input (the ip they typed)
+    // the user's input (the address they typed)
     ip: Res<IPString>,
+    mut settings: ResMut<Settings>,
+    mut error: ResMut<JoinError>,
 
     mut game_state: ResMut<NextState<GameState>>,
 
@@ -144,25 +267,17 @@ fn join_input_ip(
 ) {
     // if they press enter
     if keys.just_pressed(KeyCode::Return) {
-        // connect to the ip the user input
-        let client = renet_client(ip.0.trim());
-
-        match client {
-            // ip is ok
-            Ok(client) => {
-                // insert the client resource
-                commands.insert_resource(client);
-                // change the setting to client
-                commands.insert_resource(MultiplayerSetting(HostClient::Client));
-                // go to the next state
-                game_state.set(GameState::CheckingConnection);
-            }
-            // there is an error
-            Err(a) => match a {
-                BindError::Client => println!("client error"),
-                BindError::Server => println!("server error"),
-                BindError::Format => println!("format error"),
-            },
+        // connect to the address the user input
+        let target = JoinTarget::Address(ip.0.trim().to_string());
+        let session = rand::random();
+        if let Err(e) = connect(
+            &target,
+            session,
+            &mut commands,
+            &mut game_state,
+            &mut settings,
+        ) {
+            error.0 = e.to_string();
         }
 
     // escape goes back to the menu
@@ -175,54 +290,471 @@ fn join_input_ip(
     }
 }
 
-fn renet_client(ip: &str) -> Result<RenetClient, BindError> {
-    let split: Vec<_> = ip.split('.').collect();
-
-    // if there are 4 numbers split up by dots
-    if split.len() == 4 {
-        // parse it to an ip with the default port for a server
-        let server_addr = format!("{}:{}", ip, SERVER_PORT).parse();
-
-        // if it parsed properly
-        if let Ok(server_addr) = server_addr {
-            for i in 0..16 {
-                // try up to 16 client ports. (a single machine can have up to 16 clients running)
-
-                // listen to that socket
-                let client_addr = SocketAddr::new("0.0.0.0".parse().unwrap(), CLIENT_PORT + i);
-                // listen to the socket
-                if let Ok(socket) = UdpSocket::bind(client_addr) {
-                    // configure the connection with the currwent time, server address etc.
-                    let connection_config = RenetConnectionConfig::default();
-                    let current_time = SystemTime::now()
-                        .duration_since(SystemTime::UNIX_EPOCH)
-                        .unwrap();
-                    let client_id = current_time.as_millis() as u64;
-                    let authentication = ClientAuthentication::Unsecure {
-                        client_id,
-                        protocol_id: PROTOCOL_ID,
-                        server_addr,
-                        user_data: None,
-                    };
-
-                    // if it worked return the client structure
-                    return Ok(RenetClient::new(
-                        current_time,
-                        socket,
-                        connection_config,
-                        authentication,
-                    )
-                    .unwrap());
-                }
+// joins the server, or says why it can't. a hostname is looked up first,
+// PendingJoin carries on with the join once it has been found
+pub fn connect(
+    target: &JoinTarget,
+    session: u64,
+    commands: &mut Commands,
+    game_state: &mut NextState<GameState>,
+    settings: &mut Settings,
+) -> Result<(), BindError> {
+    // a new join replaces one that is still looking up
+    commands.remove_resource::<PendingJoin>();
+
+    match new_client(target, session, settings) {
+        Ok(NewClient::Ready(client)) => {
+            joined(target, session, *client, commands, game_state, settings);
+            Ok(())
+        }
+        Ok(NewClient::LookingUp(lookup)) => {
+            commands.insert_resource(PendingJoin {
+                target: target.clone(),
+                session,
+                lookup,
+            });
+            Ok(())
+        }
+        Err(e) => {
+            println!("could not join: {e}");
+            Err(e)
+        }
+    }
+}
+
+// a join waiting for the server's hostname to be looked up
+#[derive(Resource)]
+pub struct PendingJoin {
+    target: JoinTarget,
+    session: u64,
+    pub lookup: HostnameLookup,
+}
+
+// carries on with the join once the hostname has been looked up.
+// None while it is still looking
+pub fn poll_join(
+    pending: &PendingJoin,
+    commands: &mut Commands,
+    game_state: &mut NextState<GameState>,
+    settings: &mut Settings,
+) -> Option<Result<(), BindError>> {
+    let found = pending.lookup.poll()?;
+    commands.remove_resource::<PendingJoin>();
+
+    let client = found
+        .map_err(BindError::Address)
+        .and_then(|server_addr| address_client(server_addr, pending.session, settings));
+    match client {
+        Ok(client) => {
+            joined(
+                &pending.target,
+                pending.session,
+                client,
+                commands,
+                game_state,
+                settings,
+            );
+            Some(Ok(()))
+        }
+        Err(e) => {
+            println!("could not join: {e}");
+            Some(Err(e))
+        }
+    }
+}
+
+// stops waiting for a hostname when the player leaves the screen they joined from
+pub fn cancel_join(mut commands: Commands) {
+    commands.remove_resource::<PendingJoin>();
+}
+
+// remembers the server and joins it with the client we made
+fn joined(
+    target: &JoinTarget,
+    session: u64,
+    client: RenetClient,
+    commands: &mut Commands,
+    game_state: &mut NextState<GameState>,
+    settings: &mut Settings,
+) {
+    // tokens are already listed, they don't need to be remembered
+    if let JoinTarget::Address(address) = target {
+        settings.remember_server(address);
+    }
+    commands.insert_resource(LastServer {
+        target: target.clone(),
+        session,
+    });
+    join(commands, game_state, client, settings);
+}
+
+// connects to the server with the client we made
+fn join(
+    commands: &mut Commands,
+    game_state: &mut NextState<GameState>,
+    client: RenetClient,
+    settings: &Settings,
+) {
+    // remember the name and server for next time
+    settings.save();
+    // insert the client resource
+    commands.insert_resource(client);
+    // change the setting to client
+    commands.insert_resource(MultiplayerSetting(HostClient::Client));
+    // go to the next state
+    game_state.set(GameState::CheckingConnection);
+}
+
+// a client, or the hostname lookup that has to finish before one can be made.
+// the client is boxed because it is much bigger than the lookup
+pub enum NewClient {
+    Ready(Box<RenetClient>),
+    LookingUp(HostnameLookup),
+}
+
+// a client for the server with session as its id. tokens have the id in them
+pub fn new_client(
+    target: &JoinTarget,
+    session: u64,
+    settings: &Settings,
+) -> Result<NewClient, BindError> {
+    match target {
+        JoinTarget::Address(address) => {
+            match resolve_address(address, settings.server_port).map_err(BindError::Address)? {
+                ServerAddress::Found(server_addr) => address_client(server_addr, session, settings)
+                    .map(|client| NewClient::Ready(Box::new(client))),
+                ServerAddress::LookingUp(lookup) => Ok(NewClient::LookingUp(lookup)),
             }
-            // if it doesn't work return the corresponding error
-            Err(BindError::Client)
+        }
+        JoinTarget::Token(path) => {
+            token_client(path, settings).map(|client| NewClient::Ready(Box::new(client)))
+        }
+    }
+}
+
+// a client for a server that has been found, with session as its id
+pub fn address_client(
+    server_addr: SocketAddr,
+    session: u64,
+    settings: &Settings,
+) -> Result<RenetClient, BindError> {
+    renet_client_to(server_addr, settings, session, None)
+}
+
+// joins a secure server with a token file the host made for us
+fn token_client(path: &Path, settings: &Settings) -> Result<RenetClient, BindError> {
+    let file = TokenFile::load(path).map_err(BindError::Token)?;
+    if file.expired() {
+        return Err(BindError::Token(format!(
+            "the token for {} has run out, ask the host for a new one",
+            file.server
+        )));
+    }
+    let token = file.connect_token().map_err(BindError::Token)?;
+    renet_client_to(file.server, settings, 0, Some(token))
+}
+
+// where a typed in server is
+enum ServerAddress {
+    Found(SocketAddr),
+    // it is a hostname, which takes a while
+    LookingUp(HostnameLookup),
+}
+
+// reads "ip", "ip:port", "[ipv6]:port", "hostname" or "hostname:port", the
+// server port from the settings is used if there isn't one. hostnames are
+// looked up with the system's resolver
+fn resolve_address(text: &str, default_port: u16) -> Result<ServerAddress, String> {
+    if text.is_empty() {
+        return Err("type the address of a server".to_string());
+    }
+
+    // an ip with a port, ipv6 ones need brackets
+    if let Ok(address) = text.parse::<SocketAddr>() {
+        return Ok(ServerAddress::Found(address));
+    }
+    // an ip on its own, ipv6 ones can have brackets or not
+    let bare = text
+        .strip_prefix('[')
+        .and_then(|text| text.strip_suffix(']'))
+        .unwrap_or(text);
+    if let Ok(ip) = bare.parse::<IpAddr>() {
+        return Ok(ServerAddress::Found(SocketAddr::new(ip, default_port)));
+    }
+
+    // a hostname
+    let (host, port) = match text.rsplit_once(':') {
+        Some((host, port)) => match port.parse::<u16>() {
+            Ok(port) => (host, port),
+            Err(_) => return Err(format!("{port} is not a port number")),
+        },
+        None => (text, default_port),
+    };
+    Ok(ServerAddress::LookingUp(HostnameLookup::start(host, port)))
+}
+
+// looks up a hostname on another thread so the game doesn't freeze
+// while it waits for a slow (or missing) dns server
+pub struct HostnameLookup {
+    pub host: String,
+    found: Mutex<Receiver<Result<SocketAddr, String>>>,
+}
+
+impl HostnameLookup {
+    fn start(host: &str, port: u16) -> Self {
+        let (sender, receiver) = mpsc::channel();
+
+        let name = host.to_string();
+        thread::spawn(move || {
+            // nobody may be waiting any more, that's fine
+            let _ = sender.send(lookup_hostname(&name, port));
+        });
+
+        HostnameLookup {
+            host: host.to_string(),
+            found: Mutex::new(receiver),
+        }
+    }
+
+    // None while it is still looking
+    pub fn poll(&self) -> Option<Result<SocketAddr, String>> {
+        match self.found.lock().unwrap().try_recv() {
+            Ok(found) => Some(found),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(format!("could not find {}", self.host))),
+        }
+    }
+}
+
+// this blocks until the system's resolver answers
+fn lookup_hostname(host: &str, port: u16) -> Result<SocketAddr, String> {
+    let addresses: Vec<SocketAddr> = (host, port)
+        .to_socket_addrs()
+        .map_err(|e| format!("could not find {host}: {e}"))?
+        .collect();
+
+    // servers listen on ipv4 unless they are set up otherwise, so that is tried first
+    addresses
+        .iter()
+        .find(|address| address.is_ipv4())
+        .or_else(|| addresses.first())
+        .copied()
+        .ok_or_else(|| format!("could not find {host}"))
+}
+
+// makes a client that connects to the server at server_addr. secure servers
+// need a token, the id and name in it are used instead of client_id and the settings
+fn renet_client_to(
+    server_addr: SocketAddr,
+    settings: &Settings,
+    client_id: u64,
+    mut token: Option<ConnectToken>,
+) -> Result<RenetClient, BindError> {
+    for i in 0..CLIENT_PORT_TRIES {
+        // try the next few client ports. (a single machine can have a few clients running)
+        // there are no ports past 65535
+        let Some(port) = settings.client_port.checked_add(i) else {
+            break;
+        };
+
+        // listen to that socket
+        let client_addr = client_bind_address(server_addr.ip(), port);
+        // listen to the socket
+        if let Ok(socket) = UdpSocket::bind(client_addr) {
+            // configure the connection with the currwent time, server address etc.
+            let connection_config = RenetConnectionConfig::default();
+            let current_time = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap();
+            let authentication = match token.take() {
+                Some(connect_token) => ClientAuthentication::Secure { connect_token },
+                None => ClientAuthentication::Unsecure {
+                    client_id,
+                    protocol_id: PROTOCOL_ID,
+                    server_addr,
+                    user_data: Some(name_to_user_data(&settings.player_name)),
+                },
+            };
+
+            // if it worked return the client structure
+            let mut client =
+                RenetClient::new(current_time, socket, connection_config, authentication).unwrap();
+            send_hello(&mut client);
+            return Ok(client);
+        }
+    }
+    // if it doesn't work return the corresponding error
+    Err(BindError::Client)
+}
+
+// joins once the hostname has been looked up
+fn finish_join(
+    mut commands: Commands,
+    pending: Option<Res<PendingJoin>>,
+    mut settings: ResMut<Settings>,
+    mut error: ResMut<JoinError>,
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    let Some(pending) = pending else {
+        return;
+    };
+    if pending.is_added() {
+        error.0 = format!("looking up {}...", pending.lookup.host);
+    }
+    if let Some(Err(e)) = poll_join(&pending, &mut commands, &mut game_state, &mut settings) {
+        error.0 = e.to_string();
+    }
+}
+
+fn stop_searching(mut commands: Commands) {
+    commands.remove_resource::<LanDiscovery>();
+}
+
+// rebuilds the list when a server is found or lost. the recent servers only
+// change when we join one, which leaves the menu
+fn update_server_list(
+    mut commands: Commands,
+    discovery: Res<LanDiscovery>,
+    settings: Res<Settings>,
+    list: Query<Entity, With<ServerList>>,
+    asset_server: Res<AssetServer>,
+) {
+    if !discovery.is_changed() {
+        return;
+    }
+    let Ok(list) = list.get_single() else {
+        return;
+    };
+
+    let style = TextStyle {
+        font: asset_server.load("fonts/Rubik-SemiBold.ttf"),
+        font_size: 30.0,
+        color: Color::BLACK,
+    };
+
+    commands.entity(list).despawn_descendants();
+    commands.entity(list).with_children(|parent| {
+        if !settings.recent_servers.is_empty() {
+            parent.spawn(TextBundle::from_section("Recent servers:", style.clone()));
+            for address in &settings.recent_servers {
+                spawn_server_button(
+                    parent,
+                    TextBundle::from_section(address.clone(), style.clone()),
+                    JoinTarget::Address(address.clone()),
+                );
+            }
+        }
+
+        // tokens for secure servers, expired ones can't be used
+        let tokens = saved_tokens();
+        if !tokens.is_empty() {
+            parent.spawn(TextBundle::from_section("Secure servers:", style.clone()));
+        }
+        for (path, token) in tokens {
+            let line = format!("{} as {}", token.server, token.name);
+            if token.expired() {
+                let mut grey = style.clone();
+                grey.color = Color::GRAY;
+                parent.spawn(
+                    TextBundle::from_section(format!("{line}   (expired)"), grey).with_style(
+                        Style {
+                            padding: UiRect::all(Val::Px(5.0)),
+                            ..default()
+                        },
+                    ),
+                );
+            } else {
+                spawn_server_button(
+                    parent,
+                    TextBundle::from_section(line, style.clone()),
+                    JoinTarget::Token(path),
+                );
+            }
+        }
+
+        let title = if discovery.servers.is_empty() {
+            "Looking for games on your network..."
         } else {
-            // if it doesn't work return the corresponding error
-            Err(BindError::Server)
+            "Games on your network:"
+        };
+        parent.spawn(TextBundle::from_section(title, style.clone()));
+
+        for server in &discovery.servers {
+            let info = &server.info;
+            let compatible = PROTOCOL_VERSION.compatible_with(&info.protocol);
+            let mut line = format!(
+                "{}   {}/{} players   {} levels   v{}",
+                info.name,
+                info.players,
+                info.max_players,
+                info.map_pack.len(),
+                info.protocol
+            );
+            if !compatible {
+                line.push_str("   (different version)");
+            }
+
+            let mut text_style = style.clone();
+            if !compatible {
+                text_style.color = Color::GRAY;
+            }
+            let text = TextBundle::from_section(line, text_style);
+
+            // games on another version can't be joined so they aren't buttons
+            if compatible {
+                spawn_server_button(parent, text, JoinTarget::Address(info.address.to_string()));
+            } else {
+                parent.spawn(text.with_style(Style {
+                    padding: UiRect::all(Val::Px(5.0)),
+                    ..default()
+                }));
+            }
+        }
+    });
+}
+
+fn spawn_server_button(parent: &mut ChildBuilder, text: TextBundle, target: JoinTarget) {
+    parent
+        .spawn((
+            ButtonBundle {
+                style: Style {
+                    padding: UiRect::all(Val::Px(5.0)),
+                    ..default()
+                },
+                background_color: Color::NONE.into(),
+                ..default()
+            },
+            ServerButton(target),
+        ))
+        .with_children(|button| {
+            button.spawn(text);
+        });
+}
+
+// clicking a server joins it
+fn click_server(
+    mut commands: Commands,
+    mut buttons: Query<(&Interaction, &ServerButton, &mut BackgroundColor), Changed<Interaction>>,
+    mut settings: ResMut<Settings>,
+    mut error: ResMut<JoinError>,
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    for (interaction, button, mut colour) in buttons.iter_mut() {
+        match interaction {
+            Interaction::Clicked => {
+                let session = rand::random();
+                if let Err(e) = connect(
+                    &button.0,
+                    session,
+                    &mut commands,
+                    &mut game_state,
+                    &mut settings,
+                ) {
+                    error.0 = e.to_string();
+                }
+            }
+            Interaction::Hovered => *colour = Color::rgba(1.0, 1.0, 1.0, 0.4).into(),
+            Interaction::None => *colour = Color::NONE.into(),
         }
-    } else {
-        // if it doesn't work return the corresponding error
-        Err(BindError::Format)
     }
 }
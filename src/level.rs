@@ -0,0 +1,422 @@
+use std::{collections::VecDeque, fmt, fs, io, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+// the version of the level file format. bump this when LevelFile changes
+// so old files can still be read or rejected with a proper error
+pub const LEVEL_FORMAT_VERSION: u32 = 2;
+// version 1 wrote one digit per tile, which only has room for ten kinds
+const DIGIT_FORMAT_VERSION: u32 = 1;
+
+// every kind of tile a level can contain. the number is the code
+// that is written in the level file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Tile {
+    Empty = 0,
+    Wall = 1,
+    MovableWall = 2,
+    Spawn = 3,
+    KillerWall = 4,
+    Goal = 5,
+}
+
+impl Tile {
+    // turns a code from a level file into a tile, None if the code is unknown
+    pub fn from_code(code: u8) -> Option<Tile> {
+        match code {
+            0 => Some(Tile::Empty),
+            1 => Some(Tile::Wall),
+            2 => Some(Tile::MovableWall),
+            3 => Some(Tile::Spawn),
+            4 => Some(Tile::KillerWall),
+            5 => Some(Tile::Goal),
+            _ => None,
+        }
+    }
+
+    pub fn code(self) -> u8 {
+        self as u8
+    }
+}
+
+// a level that has been loaded and is ready to be spawned
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Level {
+    pub name: String,
+    pub author: String,
+    // how long a good run of the level takes in seconds
+    pub par_time: Option<f32>,
+    // the tile codes. the bottom row is first so the
+    // index of a row is its y position in the world
+    pub tiles: Vec<Vec<u8>>,
+}
+
+impl Level {
+    // the tile at a position in the grid (0, 0 is the bottom left)
+    pub fn tile(&self, x: usize, y: usize) -> Option<Tile> {
+        self.tiles
+            .get(y)
+            .and_then(|row| row.get(x))
+            .and_then(|code| Tile::from_code(*code))
+    }
+}
+
+// how a level is written on disk (as ron). the tiles are written as
+// one string per row with the codes split by spaces, top row first so
+// the file looks like the level
+#[derive(Debug, Serialize, Deserialize)]
+struct LevelFile {
+    version: u32,
+    name: String,
+    #[serde(default)]
+    author: String,
+    #[serde(default)]
+    par_time: Option<f32>,
+    tiles: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum LevelError {
+    // there is no level file with that name
+    NotFound,
+    Io(io::Error),
+    Ron(ron::error::SpannedError),
+    Serialize(ron::Error),
+    UnsupportedVersion(u32),
+    // a tile that couldn't be read. row and column start at 1 and
+    // count from the top left of the grid as it is written in the file
+    BadTile {
+        row: usize,
+        column: usize,
+        found: String,
+    },
+    // the level was read but it can't be played
+    Invalid(Vec<LevelProblem>),
+}
+
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LevelError::NotFound => write!(f, "level file not found"),
+            LevelError::Io(e) => write!(f, "could not read level: {e}"),
+            LevelError::Ron(e) => write!(f, "could not parse level: {e}"),
+            LevelError::Serialize(e) => write!(f, "could not write level: {e}"),
+            LevelError::UnsupportedVersion(v) => write!(
+                f,
+                "level format version {v} is not supported (expected {LEVEL_FORMAT_VERSION})"
+            ),
+            LevelError::BadTile { row, column, found } => {
+                write!(f, "row {row}, column {column}: \"{found}\" is not a tile")
+            }
+            LevelError::Invalid(problems) => {
+                for (i, problem) in problems.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{problem}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl From<io::Error> for LevelError {
+    fn from(e: io::Error) -> Self {
+        LevelError::Io(e)
+    }
+}
+
+// loads a level from a path without an extension. the .ron file is used
+// if it exists, otherwise the old .txt format is imported
+pub fn load_level(path: &str) -> Result<Level, LevelError> {
+    let ron_path = format!("{path}.ron");
+    let txt_path = format!("{path}.txt");
+
+    if Path::new(&ron_path).exists() {
+        parse_level(&fs::read_to_string(ron_path)?)
+    } else if Path::new(&txt_path).exists() {
+        let mut level = import_legacy_level(&fs::read_to_string(txt_path)?)?;
+        // old levels have no name so use the file name
+        if let Some(name) = Path::new(path).file_name() {
+            level.name = name.to_string_lossy().to_string();
+        }
+        Ok(level)
+    } else {
+        Err(LevelError::NotFound)
+    }
+}
+
+// reads a level from the contents of a .ron level file
+pub fn parse_level(contents: &str) -> Result<Level, LevelError> {
+    let file: LevelFile = ron::from_str(contents).map_err(LevelError::Ron)?;
+
+    if file.version != LEVEL_FORMAT_VERSION && file.version != DIGIT_FORMAT_VERSION {
+        return Err(LevelError::UnsupportedVersion(file.version));
+    }
+
+    let mut tiles = Vec::new();
+
+    for (row, line) in file.tiles.iter().enumerate() {
+        let parsed_line = if file.version == DIGIT_FORMAT_VERSION {
+            // one digit per tile
+            parse_codes(row, line.chars().map(|char| char.to_string()))?
+        } else {
+            parse_codes(row, line.split_whitespace())?
+        };
+        tiles.push(parsed_line);
+    }
+
+    // the file is written top row first
+    tiles.reverse();
+
+    Ok(Level {
+        name: file.name,
+        author: file.author,
+        par_time: file.par_time,
+        tiles,
+    })
+}
+
+// reads one row of tile codes, row starts at 0 from the top of the file
+fn parse_codes<S: AsRef<str>>(
+    row: usize,
+    codes: impl Iterator<Item = S>,
+) -> Result<Vec<u8>, LevelError> {
+    codes
+        .enumerate()
+        .map(|(column, code)| {
+            let code = code.as_ref();
+            code.parse::<u8>().map_err(|_| LevelError::BadTile {
+                row: row + 1,
+                column: column + 1,
+                found: code.to_string(),
+            })
+        })
+        .collect()
+}
+
+// a hash of everything in a level. it is used to check that a downloaded level
+// arrived intact and to name it in the download cache, so it is fnv-1a instead
+// of the std hasher which can change between rust versions
+pub fn level_hash(level: &Level) -> u64 {
+    // a level is only strings and numbers so this can't fail
+    let bytes = bincode::serialize(level).unwrap();
+
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+// the compatibility importer for the old whitespace separated .txt levels.
+// they have no name so it is left empty
+pub fn import_legacy_level(contents: &str) -> Result<Level, LevelError> {
+    let mut tiles = Vec::new();
+
+    // iterate over each line
+    for (row, line) in contents.trim_end().lines().enumerate() {
+        // anything that isn't a number is an error instead of a 0
+        tiles.push(parse_codes(row, line.split_whitespace())?);
+    }
+
+    tiles.reverse();
+
+    Ok(Level {
+        name: String::new(),
+        author: String::new(),
+        par_time: None,
+        tiles,
+    })
+}
+
+// writes a level to a .ron file in the current format
+pub fn save_level(level: &Level, path: &str) -> Result<(), LevelError> {
+    // turn the tiles back into strings, top row first
+    let tiles = level
+        .tiles
+        .iter()
+        .rev()
+        .map(|row| {
+            row.iter()
+                .map(|code| code.to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect();
+
+    let file = LevelFile {
+        version: LEVEL_FORMAT_VERSION,
+        name: level.name.clone(),
+        author: level.author.clone(),
+        par_time: level.par_time,
+        tiles,
+    };
+
+    let contents = ron::ser::to_string_pretty(&file, ron::ser::PrettyConfig::new())
+        .map_err(LevelError::Serialize)?;
+
+    fs::write(format!("{path}.ron"), contents)?;
+    Ok(())
+}
+
+// something that makes a level unplayable. rows and columns start at 1
+// and count from the top left of the grid as it is written in the file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LevelProblem {
+    NoSpawn,
+    ExtraSpawn {
+        row: usize,
+        column: usize,
+    },
+    NoGoal,
+    UnreachableGoal,
+    UnknownTile {
+        row: usize,
+        column: usize,
+        code: u8,
+    },
+    RaggedRow {
+        row: usize,
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl fmt::Display for LevelProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LevelProblem::NoSpawn => write!(f, "there is no spawn (3)"),
+            LevelProblem::ExtraSpawn { row, column } => write!(
+                f,
+                "row {row}, column {column}: there is already a spawn (3)"
+            ),
+            LevelProblem::NoGoal => write!(f, "there is no goal (5)"),
+            LevelProblem::UnreachableGoal => {
+                write!(f, "no goal (5) can be reached from the spawn")
+            }
+            LevelProblem::UnknownTile { row, column, code } => {
+                write!(f, "row {row}, column {column}: {code} is not a tile")
+            }
+            LevelProblem::RaggedRow {
+                row,
+                expected,
+                found,
+            } => write!(
+                f,
+                "row {row}: has {found} tiles but the first row has {expected}"
+            ),
+        }
+    }
+}
+
+// checks that a level can be played. returns every problem at once
+// so they can all be fixed before loading again
+pub fn validate_level(level: &Level) -> Result<(), Vec<LevelProblem>> {
+    let mut problems = Vec::new();
+
+    let height = level.tiles.len();
+    // the rows are stored bottom first, this turns an index
+    // into the row number that is written in the file
+    let file_row = |y: usize| height - y;
+
+    // every row should be as long as the top row
+    let expected = level.tiles.last().map(|row| row.len()).unwrap_or(0);
+
+    let mut spawn = None;
+    let mut goals = 0;
+
+    for (y, row) in level.tiles.iter().enumerate().rev() {
+        if row.len() != expected {
+            problems.push(LevelProblem::RaggedRow {
+                row: file_row(y),
+                expected,
+                found: row.len(),
+            });
+        }
+
+        for (x, code) in row.iter().enumerate() {
+            match Tile::from_code(*code) {
+                Some(Tile::Spawn) => {
+                    if spawn.is_none() {
+                        spawn = Some((x, y));
+                    } else {
+                        problems.push(LevelProblem::ExtraSpawn {
+                            row: file_row(y),
+                            column: x + 1,
+                        });
+                    }
+                }
+                Some(Tile::Goal) => goals += 1,
+                Some(_) => (),
+                None => problems.push(LevelProblem::UnknownTile {
+                    row: file_row(y),
+                    column: x + 1,
+                    code: *code,
+                }),
+            }
+        }
+    }
+
+    match spawn {
+        None => problems.push(LevelProblem::NoSpawn),
+        Some(_) if goals == 0 => problems.push(LevelProblem::NoGoal),
+        Some(spawn) => {
+            if !goal_reachable(level, spawn) {
+                problems.push(LevelProblem::UnreachableGoal)
+            }
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(problems)
+    }
+}
+
+// flood fills from the spawn to see if a goal can be touched. it ignores
+// gravity so it only catches goals that are completely walled off.
+// movable walls don't block because they can be dragged out of the way
+fn goal_reachable(level: &Level, spawn: (usize, usize)) -> bool {
+    let width = level.tiles.iter().map(|row| row.len()).max().unwrap_or(0) as i32;
+    let height = level.tiles.len() as i32;
+
+    // the player can go one tile outside of the grid, so the
+    // search area has a border around it
+    let in_bounds = |x: i32, y: i32| x >= -1 && y >= -1 && x <= width && y <= height;
+    let index = |x: i32, y: i32| ((y + 1) * (width + 2) + (x + 1)) as usize;
+
+    let mut visited = vec![false; ((width + 2) * (height + 2)) as usize];
+    let mut queue = VecDeque::new();
+
+    let start = (spawn.0 as i32, spawn.1 as i32);
+    visited[index(start.0, start.1)] = true;
+    queue.push_back(start);
+
+    while let Some((x, y)) = queue.pop_front() {
+        for (nx, ny) in [(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)] {
+            if !in_bounds(nx, ny) || visited[index(nx, ny)] {
+                continue;
+            }
+            visited[index(nx, ny)] = true;
+
+            // anything outside the grid is empty space
+            let tile = if nx >= 0 && ny >= 0 {
+                level.tile(nx as usize, ny as usize)
+            } else {
+                None
+            };
+
+            match tile {
+                Some(Tile::Goal) => return true,
+                Some(Tile::Wall) | Some(Tile::KillerWall) => (),
+                _ => queue.push_back((nx, ny)),
+            }
+        }
+    }
+
+    false
+}
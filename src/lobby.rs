@@ -0,0 +1,224 @@
+use bevy::prelude::*;
+use bevy_renet::renet::{DefaultChannel, RenetClient};
+
+use crate::{
+    hosting::HostInfo,
+    messages::{decode, ClientMessageReliable, GameMode, LobbyPlayer, ServerMessageReliable},
+    race::Race,
+    roster::{roster_event, RosterEvent},
+    startup_plugin::despawn_everything,
+    CurrentLevel, GameState, BACKGROUND_COLOUR,
+};
+
+pub struct LobbyPlugin;
+
+impl Plugin for LobbyPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(LobbyInfo::default())
+            .add_system(setup_lobby.in_schedule(OnEnter(GameState::Lobby)))
+            .add_system(lobby_update_system.in_set(OnUpdate(GameState::Lobby)))
+            .add_system(lobby_input.in_set(OnUpdate(GameState::Lobby)))
+            .add_system(update_lobby_text.in_set(OnUpdate(GameState::Lobby)))
+            .add_system(despawn_everything.in_schedule(OnExit(GameState::Lobby)));
+    }
+}
+
+// what the server last told us about the lobby
+#[derive(Resource, Default)]
+struct LobbyInfo {
+    players: Vec<LobbyPlayer>,
+    host: Option<u64>,
+    map_pack: Vec<String>,
+    mode: GameMode,
+    // whether we have said we are ready
+    ready: bool,
+}
+
+#[derive(Component)]
+struct LobbyText;
+
+fn setup_lobby(
+    mut commands: Commands,
+    mut client: ResMut<RenetClient>,
+    asset_server: Res<AssetServer>,
+) {
+    commands.insert_resource(LobbyInfo::default());
+    commands.insert_resource(ClearColor(BACKGROUND_COLOUR));
+    commands.spawn(Camera2dBundle::default());
+
+    // we have the maps, tell the server we are waiting
+    client.send_message(
+        DefaultChannel::Reliable,
+        bincode::serialize(&ClientMessageReliable::JoinLobby).unwrap(),
+    );
+
+    commands.spawn((
+        TextBundle::from_section(
+            "Lobby",
+            TextStyle {
+                font: asset_server.load("fonts/Rubik-SemiBold.ttf"),
+                font_size: 40.0,
+                color: Color::BLACK,
+            },
+        ),
+        LobbyText,
+    ));
+}
+
+fn lobby_update_system(
+    mut client: ResMut<RenetClient>,
+    mut info: ResMut<LobbyInfo>,
+    mut cl: ResMut<CurrentLevel>,
+    mut game_state: ResMut<NextState<GameState>>,
+    mut commands: Commands,
+    mut roster: EventWriter<RosterEvent>,
+) {
+    while let Some(message) = client.receive_message(DefaultChannel::Reliable) {
+        let Some(server_message) = decode::<ServerMessageReliable>(&message) else {
+            continue;
+        };
+
+        // joins and leaves go to the roster
+        if let Some(event) = roster_event(&server_message) {
+            roster.send(event);
+            continue;
+        }
+
+        match server_message {
+            ServerMessageReliable::LobbyUpdate {
+                players,
+                host,
+                map_pack,
+                mode,
+            } => {
+                info.players = players;
+                info.host = host;
+                info.map_pack = map_pack;
+                info.mode = mode;
+            }
+            // everyone starts on level 1 at the same time
+            ServerMessageReliable::StartGame => {
+                commands.insert_resource(Race::new(info.mode));
+                cl.level_number = 1;
+                game_state.set(GameState::Gameplay);
+                return;
+            }
+            _ => (),
+        }
+    }
+
+    // other players' positions aren't needed until we are playing
+    while client.receive_message(DefaultChannel::Unreliable).is_some() {}
+}
+
+fn lobby_input(
+    keys: Res<Input<KeyCode>>,
+    mut client: ResMut<RenetClient>,
+    mut info: ResMut<LobbyInfo>,
+) {
+    // r toggles ready
+    if keys.just_pressed(KeyCode::R) {
+        info.ready = !info.ready;
+        let message = ClientMessageReliable::SetReady(info.ready);
+        client.send_message(
+            DefaultChannel::Reliable,
+            bincode::serialize(&message).unwrap(),
+        );
+    }
+
+    // only the host can start or change the game mode
+    if info.host != Some(client.client_id()) {
+        return;
+    }
+
+    // enter starts the game, the server checks everyone is ready
+    if keys.just_pressed(KeyCode::Return) {
+        client.send_message(
+            DefaultChannel::Reliable,
+            bincode::serialize(&ClientMessageReliable::StartGame).unwrap(),
+        );
+    }
+
+    // m switches between free for all and race
+    if keys.just_pressed(KeyCode::M) {
+        let mode = match info.mode {
+            GameMode::FreeForAll => GameMode::Race,
+            GameMode::Race => GameMode::FreeForAll,
+        };
+        let message = ClientMessageReliable::SetGameMode(mode);
+        client.send_message(
+            DefaultChannel::Reliable,
+            bincode::serialize(&message).unwrap(),
+        );
+    }
+}
+
+fn update_lobby_text(
+    info: Res<LobbyInfo>,
+    client: Res<RenetClient>,
+    host_info: Option<Res<HostInfo>>,
+    mut text: Query<&mut Text, With<LobbyText>>,
+) {
+    let mode = match info.mode {
+        GameMode::FreeForAll => "Free for all",
+        GameMode::Race => "Race",
+    };
+    let mut value = format!(
+        "Lobby\nMode: {}\nMap pack: {} levels\n",
+        mode,
+        info.map_pack.len()
+    );
+    // tell the host what to give the other players
+    if let Some(host_info) = host_info {
+        value.push_str(&format!("Players join with: {}\n", host_info.address));
+        if !host_info.note.is_empty() {
+            value.push_str(&format!("({})\n", host_info.note));
+        }
+    }
+    for (i, name) in info.map_pack.iter().enumerate() {
+        value.push_str(&format!("  {}. {}\n", i + 1, name));
+    }
+
+    value.push_str("\nPlayers:\n");
+    for player in &info.players {
+        let status = if Some(player.id) == info.host {
+            "host"
+        } else if player.ready {
+            "ready"
+        } else {
+            "not ready"
+        };
+        let you = if player.id == client.client_id() {
+            " (you)"
+        } else {
+            ""
+        };
+        value.push_str(&format!("  {}{} - {}\n", player.name, you, status));
+    }
+
+    let everyone_ready = info
+        .players
+        .iter()
+        .all(|player| player.ready || Some(player.id) == info.host);
+
+    value.push_str("\nR: ready   Escape: leave\n");
+    if info.host == Some(client.client_id()) {
+        value.push_str("M: change mode\n");
+        if everyone_ready {
+            value.push_str("Enter: start the game");
+        } else {
+            value.push_str("Waiting for everyone to be ready");
+        }
+    } else if info.host.is_some() {
+        value.push_str("Waiting for the host to start");
+    } else {
+        value.push_str("The game starts when everyone is ready");
+    }
+
+    for mut text in &mut text {
+        // only change the text when it needs to
+        if text.sections[0].value != value {
+            text.sections[0].value = value.clone();
+        }
+    }
+}
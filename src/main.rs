@@ -1,10 +1,25 @@
 #![allow(clippy::type_complexity)]
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod anti_cheat;
+mod auth;
+mod chat;
 mod client;
+mod config;
+mod connection_lost;
+mod controls_menu;
 mod death;
+mod discovery;
+mod editor;
+mod ghost;
 mod grappling_hook;
+mod headless;
+mod hosting;
+mod input;
+mod interpolation;
 mod join_menu;
+mod level;
+mod lobby;
 mod main_menu;
 mod messages;
 mod moving_block;
@@ -12,23 +27,46 @@ mod next_level;
 mod pinging;
 mod platform;
 mod player;
+mod race;
+mod reconnect;
+mod replay;
+mod roster;
 mod server;
+mod settings;
+mod settings_menu;
 mod startup_plugin;
+mod stats;
+mod toast;
 mod win;
 mod run_if;
 
 use bevy::prelude::*;
 use bevy_rapier2d::prelude::*;
+use chat::ChatPlugin;
 use client::MyClientPlugin;
+use connection_lost::ConnectionLostPlugin;
+use controls_menu::ControlsMenuPlugin;
 use death::DeathPlugin;
+use editor::EditorPlugin;
+use ghost::GhostPlugin;
 use grappling_hook::GrapplePlugin;
+use input::ActionPlugin;
+use lobby::LobbyPlugin;
 use main_menu::{HostClient, MenuPlugin};
 use moving_block::MovingBlockPlugin;
 use next_level::NextLevelPlugin;
 use platform::PlatformPlugin;
 use player::PlayerPlugin;
+use race::RacePlugin;
+use reconnect::ReconnectPlugin;
+use replay::ReplayPlugin;
+use roster::RosterPlugin;
 use server::MyServerPlugin;
+use settings::SettingsPlugin;
+use settings_menu::SettingsMenuPlugin;
 use startup_plugin::StartupPlugin;
+use stats::StatsPlugin;
+use toast::ToastPlugin;
 use std::f32::consts::FRAC_1_SQRT_2;
 use win::WinPlugin;
 
@@ -57,12 +95,30 @@ pub enum GameState {
     Win,
     JoinMenu,
     CheckingConnection,
+    Editor,
+    Lobby,
+    Controls,
+    Settings,
+    ConnectionLost,
+    Replay,
 }
 
 #[derive(Resource)]
 pub struct MultiplayerSetting(HostClient);
 
 fn main() {
+    // --server runs a dedicated server without a window
+    let args: Vec<String> = std::env::args().collect();
+    if args.iter().any(|a| a == "--server") {
+        headless::run_headless_server(&args);
+        return;
+    }
+    // --issue-token makes a token file for a player to join a secure server
+    if args.iter().any(|a| a == "--issue-token") {
+        auth::run_issue_token(&args);
+        return;
+    }
+
     App::new()
         // add the states
         .add_state::<GameState>()
@@ -89,6 +145,21 @@ fn main() {
         .add_plugin(MyServerPlugin)
         .add_plugin(JoinMenuPlugin)
         .add_plugin(PingPlugin)
+        .add_plugin(EditorPlugin)
+        .add_plugin(LobbyPlugin)
+        .add_plugin(RacePlugin)
+        .add_plugin(ActionPlugin)
+        .add_plugin(ControlsMenuPlugin)
+        .add_plugin(SettingsPlugin)
+        .add_plugin(SettingsMenuPlugin)
+        .add_plugin(ConnectionLostPlugin)
+        .add_plugin(ReconnectPlugin)
+        .add_plugin(RosterPlugin)
+        .add_plugin(ToastPlugin)
+        .add_plugin(ChatPlugin)
+        .add_plugin(ReplayPlugin)
+        .add_plugin(GhostPlugin)
+        .add_plugin(StatsPlugin)
         
 
         // run the app
@@ -1,12 +1,17 @@
-use std::{fs::File, io::Read, u8};
-
-use bevy::{app::AppExit, prelude::*, window::PrimaryWindow};
+use bevy::{app::AppExit, ecs::system::SystemParam, prelude::*, window::PrimaryWindow};
 use bevy_renet::renet::{RenetClient, RenetServer};
 
 use crate::{
-    client::new_renet_client,
+    discovery::DiscoveryResponder,
+    editor::EditorPlaytest,
+    hosting::{local_host_ip, start_hosting, HostInfo, HostMode, PublicIpLookup},
+    input::{Action, Actions},
+    level::{load_level, validate_level, LevelError},
+    moving_block::BlockMap,
     platform::{level_directory, Maps},
-    server::new_renet_server,
+    replay::start_replay,
+    server::ServerWalls,
+    settings::Settings,
     startup_plugin::despawn_everything,
     CurrentLevel, GameState, MultiplayerSetting, BACKGROUND_COLOUR,
 };
@@ -15,31 +20,55 @@ pub struct MenuPlugin;
 
 impl Plugin for MenuPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
-        app.add_system(setup_menu.in_schedule(OnEnter(GameState::Menu)))
+        app.insert_resource(MenuMessage(String::new()))
+            .add_system(setup_menu.in_schedule(OnEnter(GameState::Menu)))
             .add_system(menu_click_system.in_set(OnUpdate(GameState::Menu)))
+            .add_system(show_menu_message.in_set(OnUpdate(GameState::Menu)))
+            .add_system(host_when_ip_found.in_set(OnUpdate(GameState::Menu)))
+            // stop waiting for the public ip if the player goes somewhere else
+            .add_system(cancel_public_ip_lookup.in_schedule(OnExit(GameState::Menu)))
             .add_system(despawn_everything.in_schedule(OnExit(GameState::Menu)))
             // go back to menu from different states
             .add_system(go_back_to_menu.in_set(OnUpdate(GameState::Gameplay)))
             .add_system(go_back_to_menu.in_set(OnUpdate(GameState::Death)))
-            .add_system(go_back_to_menu.in_set(OnUpdate(GameState::Win)));
+            .add_system(go_back_to_menu.in_set(OnUpdate(GameState::Win)))
+            .add_system(go_back_to_menu.in_set(OnUpdate(GameState::Editor)))
+            .add_system(go_back_to_menu.in_set(OnUpdate(GameState::Lobby)))
+            .add_system(go_back_to_menu.in_set(OnUpdate(GameState::Replay)));
     }
 }
 
 const PLAY: &str = "Singleplayer";
 const HOST: &str = "Host";
 const JOIN: &str = "Join";
+const EDITOR: &str = "Editor";
+const REPLAY: &str = "Replay";
+const CONTROLS: &str = "Controls";
+const SETTINGS: &str = "Settings";
 const EXIT: &str = "Exit";
 
+#[allow(clippy::too_many_arguments)]
 fn go_back_to_menu(
-    keys: Res<Input<KeyCode>>,
+    actions: Res<Actions>,
     mut game_state: ResMut<NextState<GameState>>,
     mut commands: Commands,
     mut cl: ResMut<CurrentLevel>,
     mut setting: ResMut<MultiplayerSetting>,
+    mut playtest: ResMut<EditorPlaytest>,
+    mut block_map: ResMut<BlockMap>,
+    mut server_walls: ResMut<ServerWalls>,
 ) {
-    // if escape was pressed
-    if keys.just_pressed(KeyCode::Escape) {
-        println!("escape pressed {:?}", game_state.0);
+    // if pause (escape) was pressed
+    if actions.just_pressed(Action::Pause) {
+        println!("pause pressed {:?}", game_state.0);
+
+        // go back to the editor instead if we are play-testing a level
+        if playtest.0 {
+            playtest.0 = false;
+            game_state.set(GameState::Editor);
+            return;
+        }
+
         // go back to menu
         game_state.set(GameState::Menu);
         // go bacl to level one
@@ -51,11 +80,17 @@ fn go_back_to_menu(
                 setting.0 = HostClient::Play;
                 commands.remove_resource::<RenetClient>();
                 commands.remove_resource::<RenetServer>();
+                commands.remove_resource::<HostInfo>();
+                commands.remove_resource::<DiscoveryResponder>();
+                // forget where the walls were moved to
+                block_map.blocks.clear();
+                server_walls.0.clear();
             }
             HostClient::Client => {
                 // reset to default
                 setting.0 = HostClient::Play;
                 commands.remove_resource::<RenetClient>();
+                block_map.blocks.clear();
             }
             // already default
             HostClient::Play => (),
@@ -72,6 +107,16 @@ pub enum HostClient {
 #[derive(Component)]
 pub struct Menu;
 
+// the text at the bottom of the menu that lists broken levels
+// or why the last connection failed
+#[derive(Component)]
+struct MenuMessageText;
+
+// the problems found the last time levels were loaded or why a server
+// turned us away, empty if there is nothing to show
+#[derive(Resource)]
+pub struct MenuMessage(pub String);
+
 fn setup_menu(mut commands: Commands, asset_server: Res<AssetServer>) {
     commands.insert_resource(ClearColor(BACKGROUND_COLOUR));
     commands.spawn(Camera2dBundle::default());
@@ -103,6 +148,38 @@ fn setup_menu(mut commands: Commands, asset_server: Res<AssetServer>) {
                     color: Color::BLACK,
                 },
             ),
+            TextSection::new(
+                format!("{}\n", EDITOR),
+                TextStyle {
+                    font: asset_server.load("fonts/Rubik-SemiBold.ttf"),
+                    font_size: 60.0,
+                    color: Color::BLACK,
+                },
+            ),
+            TextSection::new(
+                format!("{}\n", REPLAY),
+                TextStyle {
+                    font: asset_server.load("fonts/Rubik-SemiBold.ttf"),
+                    font_size: 60.0,
+                    color: Color::BLACK,
+                },
+            ),
+            TextSection::new(
+                format!("{}\n", CONTROLS),
+                TextStyle {
+                    font: asset_server.load("fonts/Rubik-SemiBold.ttf"),
+                    font_size: 60.0,
+                    color: Color::BLACK,
+                },
+            ),
+            TextSection::new(
+                format!("{}\n", SETTINGS),
+                TextStyle {
+                    font: asset_server.load("fonts/Rubik-SemiBold.ttf"),
+                    font_size: 60.0,
+                    color: Color::BLACK,
+                },
+            ),
             TextSection::new(
                 format!("{}\n", EXIT),
                 TextStyle {
@@ -114,16 +191,143 @@ fn setup_menu(mut commands: Commands, asset_server: Res<AssetServer>) {
         ]),
         Menu,
     ));
+
+    // spawns the (usually empty) menu message in the bottom left
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font: asset_server.load("fonts/Rubik-SemiBold.ttf"),
+                font_size: 24.0,
+                color: Color::MAROON,
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                bottom: Val::Px(10.0),
+                left: Val::Px(10.0),
+                ..default()
+            },
+            ..default()
+        }),
+        MenuMessageText,
+    ));
+}
+
+fn show_menu_message(message: Res<MenuMessage>, mut text: Query<&mut Text, With<MenuMessageText>>) {
+    for mut text in &mut text {
+        // only change the text when it needs to
+        if text.sections[0].value != message.0 {
+            text.sections[0].value = message.0.clone();
+        }
+    }
+}
+
+// everything the menu items need to do what they say
+#[derive(SystemParam)]
+pub struct MenuActions<'w, 's> {
+    commands: Commands<'w, 's>,
+    game_state: ResMut<'w, NextState<GameState>>,
+    exit: EventWriter<'w, AppExit>,
+    maps: ResMut<'w, Maps>,
+    menu_message: ResMut<'w, MenuMessage>,
+    current_level: ResMut<'w, CurrentLevel>,
+    settings: Res<'w, Settings>,
+    public_ip_lookup: Option<Res<'w, PublicIpLookup>>,
+}
+
+impl MenuActions<'_, '_> {
+    // does what the clicked menu item says
+    fn click(&mut self, item: &str) {
+        // match the menu's action
+        match item {
+            PLAY => {
+                // for debugging
+                println!("play");
+                // while there are contiguous numbered map files
+                match read_and_parse_files(1, &mut self.maps, HostClient::Play) {
+                    Ok(()) => {
+                        self.menu_message.0.clear();
+                        // sets the gamestate to gameplay
+                        self.game_state.set(GameState::Gameplay)
+                    }
+                    // stay on the menu and show what is wrong
+                    Err(errors) => self.menu_message.0 = level_errors_text(&errors),
+                }
+            }
+            HOST => {
+                println!("host");
+
+                // does the same as the singleplayer button but uses the levels
+                // in another directiory to let the player change which ones
+                // they are playing with
+                if let Err(errors) = read_and_parse_files(1, &mut self.maps, HostClient::Host) {
+                    // don't host broken levels
+                    self.menu_message.0 = level_errors_text(&errors);
+                    return;
+                }
+                self.menu_message.0.clear();
+
+                // already looking for the public ip from the last click
+                if self.public_ip_lookup.is_some() {
+                    return;
+                }
+
+                if self.settings.host_mode == HostMode::Public {
+                    // the menu keeps working while we find the public ip,
+                    // host_when_ip_found starts the server
+                    self.menu_message.0 = "finding your public ip...".to_string();
+                    self.commands.insert_resource(PublicIpLookup::start());
+                    return;
+                }
+
+                let (ip, note) = local_host_ip(&self.settings);
+                match start_hosting(&mut self.commands, &self.settings, ip, note) {
+                    Ok(()) => self.game_state.set(GameState::Lobby),
+                    Err(e) => self.menu_message.0 = e,
+                }
+            }
+            //exits the game
+            EXIT => self.exit.send(AppExit),
+            // sends us to the join menu
+            JOIN => {
+                self.game_state.set(GameState::JoinMenu);
+            }
+            // edits the singleplayer levels
+            EDITOR => {
+                // broken levels are loaded anyway so they can be fixed
+                self.menu_message.0 =
+                    match read_and_parse_files(1, &mut self.maps, HostClient::Play) {
+                        Ok(()) => String::new(),
+                        Err(errors) => level_errors_text(&errors),
+                    };
+                self.current_level.level_number = 1;
+                self.game_state.set(GameState::Editor);
+            }
+            // watch the last run that was finished
+            REPLAY => match start_replay(&mut self.commands) {
+                Ok(()) => {
+                    self.menu_message.0.clear();
+                    self.game_state.set(GameState::Replay);
+                }
+                Err(e) => self.menu_message.0 = e,
+            },
+            // change the key bindings
+            CONTROLS => self.game_state.set(GameState::Controls),
+            // change the name, ports, window and volume
+            SETTINGS => self.game_state.set(GameState::Settings),
+            // if the item pressed doesn't exist it does nothing
+            _ => (),
+        }
+    }
 }
 
 pub fn menu_click_system(
     buttons: Res<Input<MouseButton>>,
     windows: Query<&Window, With<PrimaryWindow>>,
     mut menu_items: Query<(&mut Text, &CalculatedSize), With<Menu>>,
-    mut game_state: ResMut<NextState<GameState>>,
-    mut exit: EventWriter<AppExit>,
-    mut commands: Commands,
-    mut maps: ResMut<Maps>,
+    mut actions: MenuActions,
 ) {
     let window = windows.get_single().unwrap();
 
@@ -131,10 +335,11 @@ pub fn menu_click_system(
     if let Some(position) = window.cursor_position() {
         // get the items and the size, there is only one of these so i could have used single_mut()
         for (mut items, size) in menu_items.iter_mut() {
+            let total_items = items.sections.len() as f32;
             // iterate over the sections of texy
             for (i, section) in items.sections.iter_mut().enumerate() {
                 // to find the position of text: i * size / total_items is the top y value. bottom is top - 60
-                let top = window.height() - (i as f32 * size.size.y / 4.0);
+                let top = window.height() - (i as f32 * size.size.y / total_items);
                 let bottom = top - 60.0;
 
                 // if cursor is hovering over that text
@@ -144,45 +349,7 @@ pub fn menu_click_system(
 
                     // if the user clicks on the text
                     if buttons.just_pressed(MouseButton::Left) {
-                        // match the menu's action
-                        match section.value.trim() {
-                            PLAY => {
-                                // for debugging
-                                println!("play");
-                                // while there are contiguous numbered map files
-                                read_and_parse_files(1, &mut maps, HostClient::Play);
-
-                                // sets the gamestate to gameplay
-                                game_state.set(GameState::Gameplay)
-                            }
-                            HOST => {
-                                println!("host");
-
-                                // tells the systems that we are the host
-                                commands.insert_resource(MultiplayerSetting(HostClient::Host));
-
-                                // gets the public ip and starts a renet server
-                                let rt = tokio::runtime::Runtime::new().unwrap();
-                                let public_ip = rt.block_on(public_ip::addr()).unwrap();
-                                commands.insert_resource(new_renet_client(0, public_ip));
-                                commands.insert_resource(new_renet_server(public_ip));
-
-                                // does the same as the singleplayer button but uses the levels
-                                // in another directiory to let the player change which ones
-                                // they are playing with
-                                read_and_parse_files(1, &mut maps, HostClient::Host);
-
-                                game_state.set(GameState::Gameplay);
-                            }
-                            //exits the game
-                            EXIT => exit.send(AppExit),
-                            // sends us to the join menu
-                            JOIN => {
-                                game_state.set(GameState::JoinMenu);
-                            }
-                            // if the item pressed doesn't exist it does nothing
-                            _ => (),
-                        }
+                        actions.click(section.value.trim());
                     }
                 } else {
                     // makes the text black if you dont hover over it
@@ -193,51 +360,105 @@ pub fn menu_click_system(
     }
 }
 
-fn turn_file_into_map(contents: String) -> Vec<Vec<u8>> {
-    let mut map: Vec<Vec<u8>> = Vec::new();
+// finishes hosting when the public ip lookup started by the host button is done.
+// if there is no internet it hosts on the lan instead
+fn host_when_ip_found(
+    lookup: Option<Res<PublicIpLookup>>,
+    settings: Res<Settings>,
+    mut commands: Commands,
+    mut menu_message: ResMut<MenuMessage>,
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    let Some(lookup) = lookup else {
+        return;
+    };
+    let Some(public_ip) = lookup.poll() else {
+        return;
+    };
+    commands.remove_resource::<PublicIpLookup>();
 
-    // iterate over each line
-    for line in contents.lines() {
+    let (ip, note) = match public_ip {
+        Some(ip) => (ip, String::new()),
+        None => {
+            let (ip, _) = local_host_ip(&settings);
+            (
+                ip,
+                "could not find your public ip, hosting on the LAN instead".to_string(),
+            )
+        }
+    };
 
-        // the line parsed to integers
-        let mut parsed_line = Vec::new();
+    match start_hosting(&mut commands, &settings, ip, note) {
+        Ok(()) => {
+            menu_message.0.clear();
+            game_state.set(GameState::Lobby);
+        }
+        Err(e) => menu_message.0 = e,
+    }
+}
 
-        // for each sub-string split by spaces
-        for chars in line.split(" ") {
+fn cancel_public_ip_lookup(mut commands: Commands) {
+    commands.remove_resource::<PublicIpLookup>();
+}
 
-            // if it can be parsed to an integer add the integer
-            if let Ok(int) = chars.parse::<u8>() {
-                parsed_line.push(int)
-            // otherwise add 0
-            } else {
-                parsed_line.push(0)
-            }
+// loads every level for a gamemode into the maps resource. every level is
+// checked so broken ones are reported here instead of crashing the game later
+pub fn read_and_parse_files(
+    mut cl: u8,
+    maps: &mut Maps,
+    hc: HostClient,
+) -> Result<(), Vec<(u8, LevelError)>> {
+    // get rid of the levels from the last gamemode
+    maps.maps.clear();
+
+    let mut errors = Vec::new();
 
+    loop {
+        // level_directory() function returns the directory for a specific level number and gamemode
+        match load_level(&level_directory(cl, &hc)) {
+            Ok(level) => {
+                // make sure the level can actually be played
+                if let Err(problems) = validate_level(&level) {
+                    errors.push((cl, LevelError::Invalid(problems)));
+                }
+
+                // insert the map to the maps resource to be used
+                maps.maps.insert(cl, level);
+                println!("map {cl}");
+                cl += 1;
+            }
+            // there are no more contiguous numbered map files
+            Err(LevelError::NotFound) => break,
+            // the file exists but is broken
+            Err(e) => {
+                errors.push((cl, e));
+                break;
+            }
         }
-        map.push(parsed_line);
     }
 
-    map.reverse();
+    // there has to be a first level to start on
+    if maps.maps.is_empty() && errors.is_empty() {
+        errors.push((cl, LevelError::NotFound));
+    }
 
-    map
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        for (number, e) in &errors {
+            println!("level {number}: {e}");
+        }
+        Err(errors)
+    }
 }
 
-fn read_and_parse_files(mut cl: u8, maps: &mut ResMut<Maps>, hc: HostClient) {
-    // while there is a file
-    while let Ok(mut file) =
-        // level_directory() function returns the directory for a specific level number and gamemode
-        File::open(level_directory(cl, &hc))
-    {
-        // read the contents of the file
-        let mut contents = String::new();
-        file.read_to_string(&mut contents).unwrap();
-
-        // turn the string into a map
-        let map = turn_file_into_map(contents);
-
-        // insert the map to the maps resource to be used
-        maps.maps.insert(cl, map);
-        println!("map {cl}");
-        cl += 1;
+// turns the errors from loading levels into text to show on the menu
+pub fn level_errors_text(errors: &[(u8, LevelError)]) -> String {
+    let mut text = String::new();
+    for (number, e) in errors {
+        for line in e.to_string().lines() {
+            text.push_str(&format!("level {number}: {line}\n"));
+        }
     }
+    text
 }
@@ -1,7 +1,105 @@
+use std::fmt;
+
 use crate::Vec3;
 use bevy::prelude::*;
 
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+// the renet protocol id. this never changes any more, the real version is
+// checked in the hello message so a client with the wrong version can be
+// told why it was turned away instead of the connection silently failing
+pub const PROTOCOL_ID: u64 = 6;
+
+// the version of the messages in this file.
+// bump major when a message is changed or removed (old clients can't talk to us),
+// minor when a message is added, patch for anything else
+pub const PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion {
+    major: 9,
+    minor: 0,
+    patch: 0,
+};
+
+// the most bytes of a map sent in one MapChunk message. renet's reliable
+// channel won't take messages much bigger than this
+pub const MAP_CHUNK_SIZE: usize = 1024;
+
+// display names are cut down to this many characters
+pub const MAX_NAME_LENGTH: usize = 16;
+
+// chat messages are cut down to this many characters
+pub const MAX_CHAT_LENGTH: usize = 120;
+
+// the version of the game itself, only used to tell people what to update to
+pub const GAME_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProtocolVersion {
+    pub major: u16,
+    pub minor: u16,
+    pub patch: u16,
+}
+
+impl ProtocolVersion {
+    // a client can join a server with the same major version. added messages
+    // that one side doesn't know about are dropped as malformed, not crashed on
+    pub fn compatible_with(&self, other: &ProtocolVersion) -> bool {
+        self.major == other.major
+    }
+}
+
+impl fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+// turns bytes back into a message. a bad or out of date packet
+// is logged and returns None instead of crashing the game
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Option<T> {
+    match bincode::deserialize(bytes) {
+        Ok(message) => Some(message),
+        Err(e) => {
+            println!("could not decode a message: {e}");
+            None
+        }
+    }
+}
+
+// puts a display name into the user data a client connects with.
+// the first byte is the length of the name in bytes
+pub fn name_to_user_data(name: &str) -> [u8; 256] {
+    let name = clean_name(name);
+    let mut user_data = [0; 256];
+    user_data[0] = name.len() as u8;
+    user_data[1..=name.len()].copy_from_slice(name.as_bytes());
+    user_data
+}
+
+// gets the display name back out of a client's user data
+pub fn name_from_user_data(user_data: &[u8; 256]) -> String {
+    let len = user_data[0] as usize;
+    clean_name(&String::from_utf8_lossy(&user_data[1..=len]))
+}
+
+// removes anything that would break the text on screen and cuts names that are too long
+pub fn clean_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_NAME_LENGTH)
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+// the same for chat messages, which can be longer
+pub fn clean_chat(text: &str) -> String {
+    text.chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_CHAT_LENGTH)
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
 
 // These enums are well named so I'm not commenting each individual branch. 
 
@@ -9,17 +107,17 @@ use serde::{Deserialize, Serialize};
 // channel (faster but it is possible that packets can be lost)
 #[derive(Debug, Serialize, Deserialize, Component)]
 pub enum ServerMessageUnreliable {
+    // time is when the player was there, on that player's clock
     PlayerPosition {
         id: u64,
         position: Vec3,
         level: u8,
-    },
-    Map {
-        map: Vec<Vec<u8>>,
-        number: u8,
+        time: f64,
+        velocity: Vec2,
+        facing: Facing,
+        hook: HookState,
     },
     WallPos {
-        client_id: u64,
         wall_id: i32,
         pos: Vec2,
         level: u8,
@@ -30,25 +128,160 @@ pub enum ServerMessageUnreliable {
 // slower but dropped packets are re-sent 
 #[derive(Debug, Serialize, Deserialize, Component)]
 pub enum ServerMessageReliable {
-    PlayerConnected { id: u64 },
+    // Rejected and Welcome must stay the first variants so that
+    // any version of the game can read them
+    Rejected { reason: String },
+    Welcome,
+    // someone else joined or left. sent to everyone that has said hello
+    PlayerConnected { id: u64, name: String },
     PlayerDisconnected { id: u64 },
-    DebugMessage(String),
-    NumberOfMaps(u16),
+    // everyone that is connected, sent after the Welcome
+    Roster(Vec<RosterPlayer>),
+    // something someone said, id is None if it is from the server itself.
+    // time is when the server got it, in seconds since 1970
+    Chat {
+        id: Option<u64>,
+        name: String,
+        text: String,
+        time: u64,
+    },
+    // every map the server has. the client asks for the ones it hasn't got
+    MapList(Vec<MapInfo>),
+    // part of a map, the bytes are a bincode Level. chunks
+    // of a map arrive in order, it's done when it has all `size` bytes
+    MapChunk { number: u8, bytes: Vec<u8> },
     Pong,
+    // everyone in the lobby. host is None on a dedicated server.
+    // map_pack is the name of every map in order
+    LobbyUpdate {
+        players: Vec<LobbyPlayer>,
+        host: Option<u64>,
+        map_pack: Vec<String>,
+        mode: GameMode,
+    },
+    // everyone goes to level 1 now
+    StartGame,
+    // seconds until a race starts, sent every second
+    Countdown(u8),
+    // the countdown finished, everyone can move
+    RaceStart,
+    // everyone's times so far, best first
+    Standings(Vec<Standing>),
+    // who is allowed to move a wall (None if nobody) and where it is
+    WallOwner {
+        level: u8,
+        wall_id: i32,
+        owner: Option<u64>,
+        pos: Option<Vec2>,
+    },
 }
 
 // message sent from a client through unreliable channel
 #[derive(Debug, Serialize, Deserialize, Component, Resource)]
 pub enum ClientMessageUnreliable {
-    PlayerPosition { pos: Vec3, level: u8 },
+    // time is seconds since the game started, so other clients can
+    // space the positions out properly even if packets arrive in bursts
+    PlayerPosition {
+        pos: Vec3,
+        level: u8,
+        time: f64,
+        velocity: Vec2,
+        facing: Facing,
+        hook: HookState,
+    },
     WallPos { level: u8, wall_id: i32, pos: Vec2 },
 }
 
+// which way a player's sprite is pointing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Facing {
+    Left,
+    #[default]
+    Right,
+}
+
+// what a player's grappling hook is doing
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum HookState {
+    // it isn't out
+    #[default]
+    None,
+    // it has been fired and is moving in direction
+    Flying { position: Vec2, direction: Vec2 },
+    // it has hit a wall and the player is swinging from it
+    Attached { anchor: Vec2 },
+}
+
 // message sent from a client through the reliable channel
 #[derive(Debug, Serialize, Deserialize, Component, Resource)]
 pub enum ClientMessageReliable {
-    DebugMessage(String),
+    // the first message a client sends. it must stay the first
+    // variant and never change so every version can read it
+    Hello {
+        protocol: ProtocolVersion,
+        game_version: String,
+    },
+    // something to say to everyone, the server cleans it with clean_chat
+    Chat(String),
     Ping,
+    // ask to be the one moving a wall
+    GrabWall { level: u8, wall_id: i32 },
+    // stop moving a wall, it has landed at pos
+    ReleaseWall { level: u8, wall_id: i32, pos: Vec2 },
+    // ask for the owner and position of every wall
+    RequestWalls,
+    // ask for a map from the MapList that isn't in the download cache
+    RequestMap { number: u8 },
+    // the client has all the maps and is waiting in the lobby
+    JoinLobby,
+    SetReady(bool),
+    // only listened to from the host
+    StartGame,
+    SetGameMode(GameMode),
+    // the client touched the goal of a level
+    ReachedGoal { level: u8 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum GameMode {
+    // everyone plays at their own pace
+    #[default]
+    FreeForAll,
+    // the server times everyone from the same start
+    Race,
+}
+
+// a player's place in a race
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Standing {
+    pub id: u64,
+    pub name: String,
+    // seconds from the start of the race to reaching the goal
+    // of each level, so splits[0] is level 1
+    pub splits: Vec<f32>,
+    pub finished: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LobbyPlayer {
+    pub id: u64,
+    pub name: String,
+    pub ready: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RosterPlayer {
+    pub id: u64,
+    pub name: String,
+}
+
+// a map the server has, the hash is level_hash of the map
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct MapInfo {
+    pub number: u8,
+    pub hash: u64,
+    // how many bytes the map is when it is sent
+    pub size: u32,
 }
 
 #[derive(Resource)]
@@ -1,24 +1,43 @@
 use bevy::{prelude::*, sprite::collide_aabb::collide, utils::HashMap, window::PrimaryWindow};
-use bevy_rapier2d::{dynamics::RigidBody, geometry::Collider, prelude::Velocity};
+use bevy_rapier2d::{dynamics::RigidBody, prelude::Velocity};
 use bevy_renet::renet::{DefaultChannel, RenetClient};
 
 use crate::{
-    messages::ClientMessageUnreliable, run_if::run_if_online, startup_plugin::PlayerCamera,
-    CurrentLevel, GameState, MAP_SCALE,
+    messages::{ClientMessageReliable, ClientMessageUnreliable},
+    run_if::run_if_online,
+    startup_plugin::PlayerCamera,
+    CurrentLevel, GameState,
 };
 
+// a released wall is given back to the server once it is moving slower than this
+const SETTLED_SPEED: f32 = 5.0;
+
 pub struct MovingBlockPlugin;
 
 impl Plugin for MovingBlockPlugin {
     fn build(&self, app: &mut App) {
         app.add_system(movable_walls.in_set(OnUpdate(GameState::Gameplay)))
             .add_system(moving_wall.in_set(OnUpdate(GameState::Gameplay)))
-            .add_system(spawn_multiplayer_walls.in_set(OnUpdate(GameState::Gameplay)))
+            .add_system(
+                sync_shared_walls
+                    .run_if(run_if_online)
+                    .in_set(OnUpdate(GameState::Gameplay)),
+            )
+            .add_system(
+                release_settled_walls
+                    .run_if(run_if_online)
+                    .in_set(OnUpdate(GameState::Gameplay)),
+            )
             .add_system(
                 send_block_positions
                     .run_if(run_if_online)
                     .in_set(OnUpdate(GameState::Gameplay)),
             )
+            .add_system(
+                request_walls
+                    .run_if(run_if_online)
+                    .in_schedule(OnEnter(GameState::Gameplay)),
+            )
             .insert_resource(BlockMap::new());
     }
 }
@@ -30,20 +49,31 @@ pub struct MovableWall {
 }
 
 #[derive(Component)]
-struct MultiplayerWall {
-    client_id: u64,
-    wall_id: i32,
-}
+pub struct MovingWall;
 
+// a wall that this client let go of but is still falling or sliding.
+// we keep control of it until it stops so everyone sees where it lands
 #[derive(Component)]
-pub struct MovingWall;
+struct SettlingWall;
+
+// the unique id of a movable wall. each tile in each level gets its own id,
+// and it is the same every time so every client agrees on which wall is which.
+// multiply them by different large primes to guarantee each block has
+// a unique number
+pub fn wall_id(x: f32, y: f32, level_number: u8) -> i32 {
+    (x as i32 * 1117) + (y as i32 * 4339) + (level_number as i32 * 27)
+}
 
+#[allow(clippy::too_many_arguments)]
 fn movable_walls(
     walls: Query<(&Transform, &MovableWall, Entity), Without<MovingWall>>,
     mouse: Res<Input<MouseButton>>,
     windows: Query<&Window, With<PrimaryWindow>>,
     mut commands: Commands,
     camera: Query<&Transform, With<PlayerCamera>>,
+    block_map: Res<BlockMap>,
+    level: Res<CurrentLevel>,
+    client: Option<ResMut<RenetClient>>,
 ) {
     let window = windows.get_single().unwrap();
     let camera = camera.single();
@@ -67,8 +97,32 @@ fn movable_walls(
                 )
                 .is_some()
                 {
+                    if let Some(mut client) = client {
+                        let key = (level.level_number, wall.unique_id);
+                        let owner = block_map.blocks.get(&key).and_then(|w| w.owner);
+
+                        // someone else is already dragging it
+                        if owner.is_some() && owner != Some(client.client_id()) {
+                            break;
+                        }
+
+                        // ask the server for it. we start dragging straight away and
+                        // the server tells us to let go if someone else got there first
+                        let message = ClientMessageReliable::GrabWall {
+                            level: level.level_number,
+                            wall_id: wall.unique_id,
+                        };
+                        client.send_message(
+                            DefaultChannel::Reliable,
+                            bincode::serialize(&message).unwrap(),
+                        );
+                    }
+
                     // if it intersects, the wall is moving
-                    commands.entity(entity).insert(MovingWall);
+                    commands
+                        .entity(entity)
+                        .insert(MovingWall)
+                        .remove::<SettlingWall>();
                     break;
                 }
             }
@@ -76,93 +130,125 @@ fn movable_walls(
     }
 }
 
+// the state of every shared wall that the server has told us about
+pub struct SharedWall {
+    // where the wall is, None if it hasn't moved from where the map put it
+    pub pos: Option<Vec2>,
+    // the client that is allowed to move it
+    pub owner: Option<u64>,
+}
+
 // a data structure that has a hashmap
-// the key is the level number
-// the value is a tuple of (playerid, block_id, position_of_block)
+// the key is the level number and the wall id
+// the value is the position of the wall and who is moving it
 #[derive(Resource)]
 pub struct BlockMap {
-    // level num - (playerid, block_id, pos)
-    pub blocks: HashMap<u8, Vec<(u64, i32, Vec2)>>,
+    // (level num, block_id) - wall
+    pub blocks: HashMap<(u8, i32), SharedWall>,
 }
 
-// a function to spawn the walls that other players control
-fn spawn_multiplayer_walls(
-    // the resource with the positions of the walls
+// online there is only one copy of each wall and the server decides who can move it.
+// walls we own are simulated here, everyone else's just follow the server
+fn sync_shared_walls(
+    mut walls: Query<
+        (
+            Entity,
+            &mut Transform,
+            &mut RigidBody,
+            &mut Velocity,
+            &MovableWall,
+            Option<&MovingWall>,
+            Option<&SettlingWall>,
+        ),
+        Without<PlayerCamera>,
+    >,
     block_map: Res<BlockMap>,
-
-    // the walls that have already been spawned
-    mut walls: Query<(&mut Transform, &MultiplayerWall)>,
-
-    // the level that our player is on
     current_level: Res<CurrentLevel>,
-
+    client: Res<RenetClient>,
     mut commands: Commands,
 ) {
-    // if there are any blocks already on this level that need to be spawned or updated
-    if let Some(block_vec) = block_map.blocks.get(&current_level.level_number) {
-        // makes a vector with tuples of (player_id, block_id, position, boolean)
-        // in regards to the boolean, true means it needs to be spawned
-        // false means it has already been spawned
-        let mut vec_bool: Vec<(u64, i32, Vec2, bool)> =
-            block_vec.iter().map(|a| (a.0, a.1, a.2, true)).collect();
-
-        // iterating over the walls that have already been spawned
-        for (mut transform, multiplayer) in walls.iter_mut() {
-            for i in &mut vec_bool {
-                // if the wall matches
-                if multiplayer.wall_id == i.1 && multiplayer.client_id == i.0 {
-                    // updates position
-                    transform.translation.x = i.2.x;
-                    transform.translation.y = i.2.y;
-                    // wall has been updated so boolean is set to false
-                    i.3 = false;
-                }
+    let my_id = client.client_id();
+
+    for (entity, mut transform, mut body, mut velocity, wall, moving, settling) in walls.iter_mut()
+    {
+        let shared = block_map
+            .blocks
+            .get(&(current_level.level_number, wall.unique_id));
+        let owner = shared.and_then(|w| w.owner);
+
+        // we own it, or we just grabbed it and the server hasn't answered yet
+        let ours = match owner {
+            Some(owner) => owner == my_id,
+            None => moving.is_some() || settling.is_some(),
+        };
+
+        if ours {
+            // we are in control, let the physics move it
+            if *body != RigidBody::Dynamic {
+                *body = RigidBody::Dynamic;
             }
+            continue;
         }
-        // loops over and pops the stack
-        while let Some((client_id, wall_id, pos, bool)) = vec_bool.pop() {
-            // doesn't need to be updated
-            if !bool {
-                continue;
-            } else {
-                // spawns a block with the correct components and in the right position
-                let size = Vec2::new(MAP_SCALE, MAP_SCALE);
-                commands
-                    .spawn(SpriteBundle {
-                        sprite: Sprite {
-                            color: Color::rgba(1.0, 0.1, 0.5, 0.7),
-                            custom_size: Some(size),
-                            ..default()
-                        },
-                        transform: Transform {
-                            translation: Vec3 {
-                                x: pos.x,
-                                y: pos.y,
-                                z: 15.0,
-                            },
-                            ..default()
-                        },
-                        ..Default::default()
-                    })
-                    .insert(RigidBody::Fixed)
-                    .insert(TransformBundle::from(Transform::from_xyz(
-                        pos.x, pos.y, 10.0,
-                    )))
-                    .insert(Collider::cuboid(size.x / 2.0, size.y / 2.0))
-                    .insert(MultiplayerWall { client_id, wall_id })
-                    .insert(Velocity::default());
-            }
+
+        if moving.is_some() || settling.is_some() {
+            // someone else won the wall, stop dragging it
+            commands
+                .entity(entity)
+                .remove::<MovingWall>()
+                .remove::<SettlingWall>();
+        }
+
+        // walls we don't control are moved by the server
+        if *body != RigidBody::KinematicPositionBased {
+            *body = RigidBody::KinematicPositionBased;
+        }
+        velocity.linvel = Vec2::ZERO;
+        if let Some(pos) = shared.and_then(|w| w.pos) {
+            transform.translation.x = pos.x;
+            transform.translation.y = pos.y;
         }
     }
 }
 
-// a system that sends the current positions of all visible blocks to the server.
+// gives walls back to the server once they have stopped moving
+fn release_settled_walls(
+    walls: Query<(Entity, &Transform, &Velocity, &MovableWall), With<SettlingWall>>,
+    level: Res<CurrentLevel>,
+    mut client: ResMut<RenetClient>,
+    mut commands: Commands,
+) {
+    for (entity, transform, velocity, wall) in walls.iter() {
+        if velocity.linvel.length() < SETTLED_SPEED {
+            let message = ClientMessageReliable::ReleaseWall {
+                level: level.level_number,
+                wall_id: wall.unique_id,
+                pos: transform.translation.truncate(),
+            };
+            client.send_message(
+                DefaultChannel::Reliable,
+                bincode::serialize(&message).unwrap(),
+            );
+            commands.entity(entity).remove::<SettlingWall>();
+        }
+    }
+}
+
+// asks the server where all the walls are, every time a level starts
+fn request_walls(mut client: ResMut<RenetClient>) {
+    let message = ClientMessageReliable::RequestWalls;
+    client.send_message(
+        DefaultChannel::Reliable,
+        bincode::serialize(&message).unwrap(),
+    );
+}
+
+// a system that sends the current positions of the blocks this client is moving to the server.
 fn send_block_positions(
-    walls: Query<(&Transform, &MovableWall)>,
+    walls: Query<(&Transform, &MovableWall), Or<(With<MovingWall>, With<SettlingWall>)>>,
     level: Res<CurrentLevel>,
     mut client: ResMut<RenetClient>,
 ) {
-    // iterates over all of the movable wall entities and sends the level, wall_id and position to the server
+    // iterates over the walls we are moving and sends the level, wall_id and position to the server
     for wall in walls.iter() {
         let message = ClientMessageUnreliable::WallPos {
             level: level.level_number,
@@ -180,6 +266,7 @@ fn moving_wall(
     mouse: Res<Input<MouseButton>>,
     camera: Query<&Transform, (With<PlayerCamera>, Without<MovingWall>)>,
     mut commands: Commands,
+    client: Option<Res<RenetClient>>,
 ) {
     // if there are moving walls
     if !moving_walls.is_empty() {
@@ -204,6 +291,11 @@ fn moving_wall(
             // if they are not dragging the mouse, wall is no longer moving
             for (_, entity, _) in moving_walls.iter() {
                 commands.entity(entity).remove::<MovingWall>();
+
+                // online it keeps sending its position until it lands
+                if client.is_some() {
+                    commands.entity(entity).insert(SettlingWall);
+                }
             }
         }
     }
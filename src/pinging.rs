@@ -1,13 +1,15 @@
-use std::time::SystemTime;
+use std::{fs, time::SystemTime};
 
-use bevy::prelude::*;
+use bevy::{prelude::*, utils::HashMap};
 
 use bevy_renet::renet::{DefaultChannel, RenetClient};
 
 use crate::{
-    main_menu::{HostClient, Menu},
-    messages::{ClientMessageReliable, ServerMessageReliable, ServerMessageUnreliable},
-    platform::Maps,
+    level::{level_hash, load_level, save_level, validate_level, Level, LevelError},
+    main_menu::{level_errors_text, HostClient, Menu, MenuMessage},
+    messages::{decode, ClientMessageReliable, MapInfo, ServerMessageReliable},
+    platform::{download_path, Maps, DOWNLOAD_DIRECTORY},
+    roster::{roster_event, RosterEvent},
     startup_plugin::despawn_everything,
     GameState, MultiplayerSetting,
 };
@@ -25,8 +27,32 @@ enum PingStage {
     RequestingMaps,
 }
 
-#[derive(Resource)]
-struct NumberOfMaps(Option<u16>);
+// the maps being downloaded from the server
+#[derive(Resource, Default)]
+struct MapDownload {
+    // every map the server has, None until it has told us
+    list: Option<Vec<MapInfo>>,
+    // the bytes received so far of the maps that are still downloading
+    partial: HashMap<u8, Vec<u8>>,
+    // how many bytes of maps we have, including ones that were cached
+    received: u32,
+}
+
+impl MapDownload {
+    // how much of the map pack we have, from 0 to 1
+    fn progress(&self) -> f32 {
+        let total: u32 = self.list.iter().flatten().map(|info| info.size).sum();
+        if total == 0 {
+            0.0
+        } else {
+            self.received as f32 / total as f32
+        }
+    }
+}
+
+// the filled in part of the download progress bar
+#[derive(Component)]
+struct ProgressBar;
 
 #[derive(Resource)]
 struct PingThing(PingStage);
@@ -36,12 +62,12 @@ pub struct PingPlugin;
 impl Plugin for PingPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
         app.insert_resource(PingThing(PingStage::Pinging))
-            // initialise resource to none
-            .insert_resource(NumberOfMaps(None))
+            .insert_resource(MapDownload::default())
             // add the systems
             .add_system(setup_pinging.in_schedule(OnEnter(GameState::CheckingConnection)))
             .add_system(listen_for_pong.in_set(OnUpdate(GameState::CheckingConnection)))
             .add_system(pinging_text.in_set(OnUpdate(GameState::CheckingConnection)))
+            .add_system(update_progress_bar.in_set(OnUpdate(GameState::CheckingConnection)))
             .add_system(despawn_everything.in_schedule(OnExit(GameState::CheckingConnection)));
     }
 }
@@ -53,6 +79,7 @@ fn setup_pinging(
 ) {
     // a resource to keep track of where we are. pinging or requesting maps
     commands.insert_resource(PingThing(PingStage::Pinging));
+    commands.insert_resource(MapDownload::default());
     commands.spawn(Camera2dBundle::default());
 
     // send the ping
@@ -77,6 +104,42 @@ fn setup_pinging(
         ),
         Menu,
     ));
+
+    // the progress bar for downloading maps, under the text
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(90.0),
+                    left: Val::Px(10.0),
+                    ..default()
+                },
+                size: Size::new(Val::Px(400.0), Val::Px(30.0)),
+                ..default()
+            },
+            background_color: Color::GRAY.into(),
+            ..default()
+        })
+        .with_children(|parent| {
+            parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Percent(0.0), Val::Percent(100.0)),
+                        ..default()
+                    },
+                    background_color: Color::BLACK.into(),
+                    ..default()
+                },
+                ProgressBar,
+            ));
+        });
+}
+
+fn update_progress_bar(download: Res<MapDownload>, mut bar: Query<&mut Style, With<ProgressBar>>) {
+    for mut style in &mut bar {
+        style.size.width = Val::Percent(download.progress() * 100.0);
+    }
 }
 
 fn pinging_text(
@@ -135,15 +198,31 @@ fn listen_for_pong(
     mut client: ResMut<RenetClient>,
     ping_time: Res<PingTime>,
     mut commands: Commands,
-    mut num_maps: ResMut<NumberOfMaps>,
+    mut download: ResMut<MapDownload>,
     mut game_state: ResMut<NextState<GameState>>,
     mut maps: ResMut<Maps>,
+    mut roster: EventWriter<RosterEvent>,
 ) {
     // recieve all messages
     while let Some(message) = client.receive_message(DefaultChannel::Reliable) {
-        let server_message: ServerMessageReliable = bincode::deserialize(&message).unwrap();
+        let Some(server_message) = decode::<ServerMessageReliable>(&message) else {
+            continue;
+        };
+
+        // the roster comes straight after the welcome
+        if let Some(event) = roster_event(&server_message) {
+            roster.send(event);
+            continue;
+        }
 
         match server_message {
+            // the server is a different version, show why on the menu
+            ServerMessageReliable::Rejected { reason } => {
+                println!("rejected by the server: {reason}");
+                back_to_menu(&mut commands, &mut game_state, reason);
+                return;
+            }
+            ServerMessageReliable::Welcome => println!("the server accepted our hello"),
             // if its a pong
             ServerMessageReliable::Pong => {
                 let ping = SystemTime::now().duration_since(ping_time.time).unwrap();
@@ -152,37 +231,120 @@ fn listen_for_pong(
                 println!("setting pingthing to request maps");
                 commands.insert_resource(PingThing(PingStage::RequestingMaps))
             }
-            // for debugging
-            ServerMessageReliable::DebugMessage(string) => {
-                println!("recieved debug message (pinging.rs) {}", string)
+            // the maps the server has. ask for the ones that aren't cached
+            ServerMessageReliable::MapList(list) => {
+                maps.maps.clear();
+
+                for info in &list {
+                    if let Some(map) = load_cached_map(info) {
+                        println!("map {} is already downloaded", info.number);
+                        download.received += info.size;
+                        maps.maps.insert(info.number, map);
+                    } else {
+                        download.partial.insert(info.number, Vec::new());
+                        let message = ClientMessageReliable::RequestMap {
+                            number: info.number,
+                        };
+                        client.send_message(
+                            DefaultChannel::Reliable,
+                            bincode::serialize(&message).unwrap(),
+                        );
+                    }
+                }
+
+                download.list = Some(list);
+            }
+            // the next part of a map
+            ServerMessageReliable::MapChunk { number, bytes } => {
+                let Some(info) = download
+                    .list
+                    .iter()
+                    .flatten()
+                    .find(|info| info.number == number)
+                    .copied()
+                else {
+                    continue;
+                };
+                let Some(partial) = download.partial.get_mut(&number) else {
+                    continue;
+                };
+                partial.extend_from_slice(&bytes);
+                let done = partial.len() >= info.size as usize;
+                download.received += bytes.len() as u32;
+
+                // wait for the rest of the map
+                if !done {
+                    continue;
+                }
+                let bytes = download.partial.remove(&number).unwrap();
+
+                match finish_download(&info, &bytes) {
+                    Ok(map) => {
+                        println!("Just got sent map number {}", number);
+                        maps.maps.insert(number, map);
+                    }
+                    // don't play a broken map, show what is wrong on the menu instead
+                    Err(error) => {
+                        back_to_menu(&mut commands, &mut game_state, error);
+                        return;
+                    }
+                }
             }
-            // the number of maps so we know we have them all
-            ServerMessageReliable::NumberOfMaps(total) => num_maps.0 = Some(total),
             _ => (),
         }
     }
 
-    // pops messages off the stack until the stack is empty
-    while let Some(message) = client.receive_message(DefaultChannel::Unreliable) {
-        let server_message: ServerMessageUnreliable = bincode::deserialize(&message).unwrap();
-
-        // recieve messages from the server
-        if let ServerMessageUnreliable::Map { map, number } = server_message {
-            // read messages containing maps
-            
-            // if we have recieved the message for the total number of maps
-            if let Some(num) = num_maps.0 {
-                // print which map we just recieved
-                println!("Just got sent map number {}", number);
-                // add it to the hashmap of maps
-                maps.maps.insert(number, map);
-
-                // if we have all of the maps go to the gameplay state
-                if maps.maps.len() == num as usize {
-                    println!("got all the maps, going to gameplay");
-                    game_state.set(GameState::Gameplay);
-                }
-            }
+    // other players' positions aren't needed until we are playing
+    while client.receive_message(DefaultChannel::Unreliable).is_some() {}
+
+    // if we have all of the maps wait in the lobby
+    if let Some(list) = &download.list {
+        if maps.maps.len() == list.len() {
+            println!("got all the maps, going to the lobby");
+            game_state.set(GameState::Lobby);
         }
     }
 }
+
+// gets a map out of the download cache if it is there and hasn't been changed
+fn load_cached_map(info: &MapInfo) -> Option<Level> {
+    let map = load_level(&download_path(info.hash)).ok()?;
+    if level_hash(&map) == info.hash && validate_level(&map).is_ok() {
+        Some(map)
+    } else {
+        None
+    }
+}
+
+// checks a map that has finished downloading and puts it in the cache
+fn finish_download(info: &MapInfo, bytes: &[u8]) -> Result<Level, String> {
+    let number = info.number;
+    let Some(map) = decode::<Level>(bytes) else {
+        return Err(format!("level {number}: could not be read"));
+    };
+    if level_hash(&map) != info.hash {
+        return Err(format!("level {number}: was corrupted while downloading"));
+    }
+    if let Err(problems) = validate_level(&map) {
+        let errors = vec![(number, LevelError::Invalid(problems))];
+        return Err(level_errors_text(&errors));
+    }
+
+    // the game still works without the cache, it just downloads again next time
+    let saved = fs::create_dir_all(DOWNLOAD_DIRECTORY)
+        .map_err(LevelError::from)
+        .and_then(|_| save_level(&map, &download_path(info.hash)));
+    if let Err(e) = saved {
+        println!("could not cache level {number}: {e}");
+    }
+
+    Ok(map)
+}
+
+// gives up on joining and shows a message on the menu
+fn back_to_menu(commands: &mut Commands, game_state: &mut NextState<GameState>, message: String) {
+    commands.insert_resource(MenuMessage(message));
+    game_state.set(GameState::Menu);
+    commands.insert_resource(MultiplayerSetting(HostClient::Play));
+    commands.remove_resource::<RenetClient>();
+}
@@ -1,8 +1,17 @@
-use crate::{main_menu::HostClient, moving_block::MovableWall, FELLA_SPRITE_SIZE};
+use crate::{
+    editor::EditorPlaytest,
+    level::{Level, Tile},
+    main_menu::HostClient,
+    messages::ClientMessageReliable,
+    moving_block::MovableWall,
+    reconnect::Reconnecting,
+    FELLA_SPRITE_SIZE,
+};
 
 use crate::{player::Player, startup_plugin::GameTextures, CurrentLevel, GameState, MAP_SCALE};
 use bevy::{prelude::*, sprite::collide_aabb::collide, utils::HashMap};
 use bevy_rapier2d::prelude::*;
+use bevy_renet::renet::{DefaultChannel, RenetClient};
 
 #[derive(Component)]
 pub struct KillerWall {
@@ -16,7 +25,7 @@ pub struct Wall {
 
 #[derive(Component)]
 pub struct Goal {
-    size: Vec2,
+    pub size: Vec2,
 }
 
 pub struct PlatformPlugin;
@@ -26,20 +35,31 @@ impl Plugin for PlatformPlugin {
         app.insert_resource(Maps {
             maps: HashMap::new(),
         })
+        // replaced when a level is spawned
+        .insert_resource(LowestPoint { point: 0.0 })
         .add_system(platform_from_map_system.in_schedule(OnEnter(GameState::Gameplay)))
         .add_system(next_level_system.in_set(OnUpdate(GameState::Gameplay)));
     }
 }
 
-// this function returns a level directory based on the setting and the level number
+// this function returns a level directory based on the setting and the level number.
+// it has no extension because levels can be .ron files or old .txt files
 pub fn level_directory(level_number: u8, hc: &HostClient) -> String {
     match hc {
-        HostClient::Client => format!("assets/levels/downloads/level-{}.txt", level_number),
-        HostClient::Host => format!("assets/levels/multiplayer/level-{}.txt", level_number),
-        HostClient::Play => format!("assets/levels/level-{}.txt", level_number),
+        HostClient::Client => format!("{}/level-{}", DOWNLOAD_DIRECTORY, level_number),
+        HostClient::Host => format!("assets/levels/multiplayer/level-{}", level_number),
+        HostClient::Play => format!("assets/levels/level-{}", level_number),
     }
 }
 
+// where maps downloaded from a server are cached
+pub const DOWNLOAD_DIRECTORY: &str = "assets/levels/downloads";
+
+// the path (without an extension) of a downloaded map, named by its hash
+pub fn download_path(hash: u64) -> String {
+    format!("{DOWNLOAD_DIRECTORY}/{hash:016x}")
+}
+
 // macro to create a normal white wall
 macro_rules! create_wall {
     ($commands:expr, $x:expr, $y:expr, $size:expr) => {{
@@ -117,10 +137,8 @@ macro_rules! create_killer_wall {
 macro_rules! create_movable_wall {
     ($commands:expr, $x:expr, $y:expr, $size:expr, $level_number:expr) => {{
 
-        // multiply them by different large primes to guarantee each block has 
-        // a unique number, but each block has the same number every time.
         // movable walls need unique identifiers for multiplayer mode.
-        let n1: i32 = ($x as i32 * 1117) + ($y as i32 * 4339) + ($level_number as i32 * 27);
+        let n1: i32 = $crate::moving_block::wall_id($x, $y, $level_number);
 
         $commands
             .spawn(SpriteBundle {
@@ -147,6 +165,9 @@ macro_rules! create_movable_wall {
     }};
 }
 
+// the editor spawns levels with the same macros
+pub(crate) use {create_killer_wall, create_level_end, create_movable_wall, create_wall};
+
 // this resource tells us the lowest point so the player despawns 
 // when it falls off of the map
 #[derive(Resource)]
@@ -156,8 +177,20 @@ pub struct LowestPoint {
 
 #[derive(Resource)]
 pub struct Maps {
-    // a vector of all of the maps
-    pub maps: HashMap<u8, Vec<Vec<u8>>>,
+    // all of the levels, the key is the level number
+    pub maps: HashMap<u8, Level>,
+}
+
+impl Maps {
+    // the name of every level, in order
+    pub fn names(&self) -> Vec<String> {
+        let mut numbers: Vec<&u8> = self.maps.keys().collect();
+        numbers.sort();
+        numbers
+            .into_iter()
+            .map(|number| self.maps[number].name.clone())
+            .collect()
+    }
 }
 
 fn platform_from_map_system(
@@ -165,13 +198,35 @@ fn platform_from_map_system(
     game_textures: Res<GameTextures>,
     current_level: Res<CurrentLevel>,
     maps: Res<Maps>,
+    mut game_state: ResMut<NextState<GameState>>,
 ) {
     // get the map
-    let map = maps
-        .maps
-        .get(&(current_level.level_number))
-        .unwrap()
-        .clone();
+    let Some(level) = maps.maps.get(&(current_level.level_number)) else {
+        // levels are checked when they are loaded so this shouldn't happen
+        println!(
+            "level {} does not exist, going back to the menu",
+            current_level.level_number
+        );
+        game_state.set(GameState::Menu);
+        return;
+    };
+    spawn_level(
+        &mut commands,
+        &game_textures,
+        level,
+        current_level.level_number,
+    );
+}
+
+// spawns the walls, goals and player of a level. replays use it too so they
+// look the same as the game
+pub fn spawn_level(
+    commands: &mut Commands,
+    game_textures: &GameTextures,
+    level: &Level,
+    level_number: u8,
+) {
+    let map = &level.tiles;
 
     // get the lowest point on the map
     commands.insert_resource(LowestPoint {
@@ -187,56 +242,63 @@ fn platform_from_map_system(
             let x = x as f32 * MAP_SCALE;
             let y = y as f32 * MAP_SCALE;
 
-            if *val == 1 {
-                // spawn a normal wall
-                create_wall!(commands, x, y, Vec2::new(MAP_SCALE, MAP_SCALE))
-            } else if *val == 2 {
-                // spawn a movable wall
-                create_movable_wall!(
-                    commands,
-                    x,
-                    y,
-                    Vec2::new(MAP_SCALE, MAP_SCALE),
-                    current_level.level_number
-                )
-            } else if *val == 3 && !player_spawned {
+            match Tile::from_code(*val) {
+                Some(Tile::Wall) => {
+                    // spawn a normal wall
+                    create_wall!(commands, x, y, Vec2::new(MAP_SCALE, MAP_SCALE))
+                }
+                Some(Tile::MovableWall) => {
+                    // spawn a movable wall
+                    create_movable_wall!(
+                        commands,
+                        x,
+                        y,
+                        Vec2::new(MAP_SCALE, MAP_SCALE),
+                        level_number
+                    )
+                }
+                Some(Tile::Spawn) if !player_spawned => {
+                    player_spawned = true;
 
-                player_spawned = true;
-
-                // SPAWN A PLAYER
-                commands
-                    .spawn(SpriteBundle {
-                        texture: game_textures.player.clone(),
-                        sprite: Sprite {
-                            custom_size: Some(FELLA_SPRITE_SIZE),
+                    // SPAWN A PLAYER
+                    commands
+                        .spawn(SpriteBundle {
+                            texture: game_textures.player.clone(),
+                            sprite: Sprite {
+                                custom_size: Some(FELLA_SPRITE_SIZE),
+                                ..Default::default()
+                            },
+                            ..Default::default()
+                        })
+                        .insert(Player::default())
+                        .insert(Collider::cuboid(
+                            FELLA_SPRITE_SIZE.x / 2.0,
+                            FELLA_SPRITE_SIZE.y / 2.0,
+                        ))
+                        .insert(KinematicCharacterController {
+                            autostep: Some(CharacterAutostep {
+                                max_height: CharacterLength::Absolute(0.5),
+                                min_width: CharacterLength::Absolute(0.2),
+                                include_dynamic_bodies: true,
+                            }),
+                            apply_impulse_to_dynamic_bodies: true,
+                            snap_to_ground: Some(CharacterLength::Absolute(0.1)),
+                            custom_mass: Some(1000.0),
                             ..Default::default()
-                        },
-                        ..Default::default()
-                    })
-                    .insert(Player::default())
-                    .insert(Collider::cuboid(
-                        FELLA_SPRITE_SIZE.x / 2.0,
-                        FELLA_SPRITE_SIZE.y / 2.0,
-                    ))
-                    .insert(KinematicCharacterController {
-                        autostep: Some(CharacterAutostep {
-                            max_height: CharacterLength::Absolute(0.5),
-                            min_width: CharacterLength::Absolute(0.2),
-                            include_dynamic_bodies: true,
-                        }),
-                        apply_impulse_to_dynamic_bodies: true,
-                        snap_to_ground: Some(CharacterLength::Absolute(0.1)),
-                        custom_mass: Some(1000.0),
-                        ..Default::default()
-                    })
-                    .insert(KinematicCharacterControllerOutput::default())
-                    .insert(TransformBundle::from(Transform::from_xyz(x, y, 10.0)));
-            } else if *val == 4 {
-                // Spawn a killer wall Thats slightly smaller than the other blocks in height
-                create_killer_wall!(commands, x, y, Vec2::new(MAP_SCALE, MAP_SCALE - 10.0))
-            } else if *val == 5 {
-                // spawn a goal
-                create_level_end!(commands, x, y, Vec2::new(MAP_SCALE, MAP_SCALE))
+                        })
+                        .insert(KinematicCharacterControllerOutput::default())
+                        .insert(TransformBundle::from(Transform::from_xyz(x, y, 10.0)));
+                }
+                Some(Tile::KillerWall) => {
+                    // Spawn a killer wall Thats slightly smaller than the other blocks in height
+                    create_killer_wall!(commands, x, y, Vec2::new(MAP_SCALE, MAP_SCALE - 10.0))
+                }
+                Some(Tile::Goal) => {
+                    // spawn a goal
+                    create_level_end!(commands, x, y, Vec2::new(MAP_SCALE, MAP_SCALE))
+                }
+                // empty tiles and extra spawns
+                _ => (),
             }
         }
     }
@@ -247,8 +309,14 @@ fn next_level_system(
     goals: Query<(&Goal, &Transform)>,
     mut level: ResMut<CurrentLevel>,
     mut game_state: ResMut<NextState<GameState>>,
+    mut playtest: ResMut<EditorPlaytest>,
+    mut client: Option<ResMut<RenetClient>>,
+    mut reconnecting: Option<ResMut<Reconnecting>>,
 ) {
-    let (player, player_transform) = player.single();
+    // there is no player if the level didn't load
+    let Ok((player, player_transform)) = player.get_single() else {
+        return;
+    };
     for (goal, goal_transform) in goals.iter() {
         // if the player collides with the goal
         if collide(
@@ -259,10 +327,33 @@ fn next_level_system(
         )
         .is_some()
         {
+            // finishing a play-test goes back to the editor
+            if playtest.0 {
+                playtest.0 = false;
+                game_state.set(GameState::Editor);
+                return;
+            }
+
+            // the server checks levels are finished in order and times races
+            if let Some(client) = client.as_mut() {
+                let message = ClientMessageReliable::ReachedGoal {
+                    level: level.level_number,
+                };
+                client.send_message(
+                    DefaultChannel::Reliable,
+                    bincode::serialize(&message).unwrap(),
+                );
+            } else if let Some(reconnecting) = reconnecting.as_mut() {
+                // tell the server once we are back, or it thinks we are still on this level
+                reconnecting.goals.push(level.level_number);
+            }
+
             // increment the level number
             level.level_number += 1;
             // go to the next level stage
-            game_state.set(GameState::NextLevel)
+            game_state.set(GameState::NextLevel);
+            // touching two goals at once only counts once
+            return;
         }
     }
 }
@@ -6,7 +6,10 @@ use bevy_rapier2d::prelude::{KinematicCharacterController, KinematicCharacterCon
 
 use crate::{
     grappling_hook::{Hook, MovingGrappleHook},
+    input::{Action, Actions},
+    messages::Facing,
     platform::{KillerWall, LowestPoint},
+    run_if::run_if_not_waiting_for_race,
     GameState, FELLA_SPRITE_SIZE, GRAPPLE_SPEED, GRAVITY_CONSTANT, PLAYER_JUMP_VELOCITY,
     PLAYER_RUN_SPEED,
 };
@@ -15,18 +18,28 @@ pub struct PlayerPlugin;
 
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(rapier_player_movement.in_set(OnUpdate(GameState::Gameplay)))
+        app.add_event::<PlayerDied>()
+            .add_system(
+                rapier_player_movement
+                    .in_set(OnUpdate(GameState::Gameplay))
+                    .run_if(run_if_not_waiting_for_race),
+            )
+            .add_system(face_the_way_the_player_moves.in_set(OnUpdate(GameState::Gameplay)))
             .add_system(player_death_fall_off_the_map.in_set(OnUpdate(GameState::Gameplay)))
             .add_system(killer_wall.in_set(OnUpdate(GameState::Gameplay)));
     }
 }
 
+// sent when the player falls off the map or touches a killer wall
+pub struct PlayerDied;
+
 #[derive(Component)]
 pub struct Player {
     pub run_speed: f32,
     pub velocity: Vec2,
     pub jump_velocity: f32,
     pub size: Vec2,
+    pub facing: Facing,
 }
 
 impl Player {
@@ -37,6 +50,7 @@ impl Player {
             velocity: Vec2::ZERO,
             jump_velocity: PLAYER_JUMP_VELOCITY,
             size: FELLA_SPRITE_SIZE,
+            facing: Facing::Right,
         }
     }
 }
@@ -49,8 +63,10 @@ pub fn rapier_player_movement(
         &KinematicCharacterControllerOutput,
         &Transform,
     )>,
-    // keyboard input
+    // keyboard input, for debugging
     keys: Res<Input<KeyCode>>,
+    // the controls being pressed
+    actions: Res<Actions>,
     // the resource that gives me access to the time since the last update
     time: Res<Time>,
     // the query for the grappling hook
@@ -75,11 +91,11 @@ pub fn rapier_player_movement(
             // if the player hits the ceiling y velocity is set to 0
             player.velocity.y = 0.0;
         }
-        if keys.pressed(KeyCode::D) {
+        if actions.pressed(Action::MoveRight) {
             // move right
             movement += Vec2::new(player.run_speed, 0.0);
         }
-        if keys.pressed(KeyCode::A) {
+        if actions.pressed(Action::MoveLeft) {
             // move left
             movement += Vec2::new(-player.run_speed, 0.0);
         }
@@ -90,7 +106,7 @@ pub fn rapier_player_movement(
             // if player is on the floor, x velocity is set to 0 (friction)
             player.velocity.x = 0.0;
 
-            if keys.pressed(KeyCode::Space) {
+            if actions.pressed(Action::Jump) {
                 // player jumps
                 player.velocity.y = player.jump_velocity;
             } else {
@@ -98,6 +114,13 @@ pub fn rapier_player_movement(
                 player.velocity.y = 0.0;
             }
         }
+        // keep facing the last way they ran
+        if movement.x > 0.0 {
+            player.facing = Facing::Right;
+        } else if movement.x < 0.0 {
+            player.facing = Facing::Left;
+        }
+
         // add the velocity to the movement
         movement += player.velocity;
 
@@ -124,7 +147,7 @@ pub fn rapier_player_movement(
 
             movement = resolved;
 
-            if keys.pressed(KeyCode::W) {
+            if actions.pressed(Action::Reel) {
                 // divide the direction by its magnitude (calculated by pythagoras' theorem)
                 direction /= direction.distance(Vec2::ZERO);
                 // add this movement towards the hook to the total player movement
@@ -155,15 +178,26 @@ fn resolve_forces(hook_direction: Vec2, velocity: Vec2) -> Vec2 {
     )
 }
 
+// flips the sprite when the player turns around
+fn face_the_way_the_player_moves(mut player: Query<(&Player, &mut Sprite), Changed<Player>>) {
+    for (player, mut sprite) in player.iter_mut() {
+        sprite.flip_x = player.facing == Facing::Left;
+    }
+}
+
 fn player_death_fall_off_the_map(
     player: Query<&Transform, With<Player>>,
     lowest_point: Res<LowestPoint>,
     mut game_state: ResMut<NextState<GameState>>,
+    mut died: EventWriter<PlayerDied>,
 ) {
     // if the player falles lower than the lowest point on the map it dies
-    let player = player.single();
+    let Ok(player) = player.get_single() else {
+        return;
+    };
     if player.translation.y <= -lowest_point.point {
-        game_state.set(GameState::Death)
+        game_state.set(GameState::Death);
+        died.send(PlayerDied);
     }
 }
 
@@ -171,8 +205,11 @@ fn killer_wall(
     walls: Query<(&KillerWall, &Transform)>,
     player: Query<(&Transform, &Player)>,
     mut game_state: ResMut<NextState<GameState>>,
+    mut died: EventWriter<PlayerDied>,
 ) {
-    let player = player.single();
+    let Ok(player) = player.get_single() else {
+        return;
+    };
 
     // if the player intersects with a killer wall it dies
     for wall in walls.iter() {
@@ -184,7 +221,10 @@ fn killer_wall(
         )
         .is_some()
         {
-            game_state.set(GameState::Death)
+            game_state.set(GameState::Death);
+            died.send(PlayerDied);
+            // touching two killer walls is still one death
+            return;
         }
     }
 }
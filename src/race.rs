@@ -0,0 +1,152 @@
+use bevy::{prelude::*, time::Stopwatch};
+
+use crate::{
+    messages::{GameMode, Standing},
+    GameState,
+};
+
+// how long "GO!" stays on screen after the countdown
+const GO_TEXT_DURATION: f32 = 1.0;
+
+pub struct RacePlugin;
+
+impl Plugin for RacePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Race::default())
+            // leaving the game forgets the race
+            .add_system(reset_race.in_schedule(OnEnter(GameState::Menu)))
+            .add_system(setup_race_text.in_schedule(OnEnter(GameState::Gameplay)))
+            .add_system(race_clock)
+            .add_system(update_race_text.in_set(OnUpdate(GameState::Gameplay)));
+    }
+}
+
+// what the client knows about the race it is in. the server does the timing,
+// this is just for showing it
+#[derive(Resource, Default)]
+pub struct Race {
+    // the game was started in race mode
+    pub racing: bool,
+    // the countdown hasn't finished, the player can't move yet
+    pub waiting: bool,
+    // the number the server last counted down
+    pub countdown: Option<u8>,
+    // the time since the server said go
+    pub clock: Option<Stopwatch>,
+    // everyone's times, best first
+    pub standings: Vec<Standing>,
+}
+
+impl Race {
+    pub fn new(mode: GameMode) -> Self {
+        let racing = mode == GameMode::Race;
+        Race {
+            racing,
+            waiting: racing,
+            ..default()
+        }
+    }
+
+    // the countdown finished
+    pub fn start(&mut self) {
+        self.waiting = false;
+        self.countdown = None;
+        self.clock = Some(Stopwatch::new());
+    }
+}
+
+#[derive(Component)]
+struct CountdownText;
+
+#[derive(Component)]
+struct RaceClockText;
+
+fn reset_race(mut commands: Commands) {
+    commands.insert_resource(Race::default());
+}
+
+fn race_clock(time: Res<Time>, mut race: ResMut<Race>) {
+    // ticking the clock doesn't count as a change, the results table
+    // is only rebuilt when the server sends new times
+    if let Some(clock) = &mut race.bypass_change_detection().clock {
+        clock.tick(time.delta());
+    }
+}
+
+fn setup_race_text(mut commands: Commands, race: Res<Race>, asset_server: Res<AssetServer>) {
+    if !race.racing {
+        return;
+    }
+
+    // the countdown in the middle of the screen
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font: asset_server.load("fonts/Rubik-SemiBold.ttf"),
+                font_size: 120.0,
+                color: Color::BLACK,
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                top: Val::Percent(35.0),
+                left: Val::Percent(45.0),
+                ..default()
+            },
+            ..default()
+        }),
+        CountdownText,
+    ));
+
+    // the race time in the top right
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font: asset_server.load("fonts/Rubik-SemiBold.ttf"),
+                font_size: 40.0,
+                color: Color::BLACK,
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                top: Val::Px(10.0),
+                right: Val::Px(10.0),
+                ..default()
+            },
+            ..default()
+        }),
+        RaceClockText,
+    ));
+}
+
+fn update_race_text(
+    race: Res<Race>,
+    mut countdown_text: Query<&mut Text, (With<CountdownText>, Without<RaceClockText>)>,
+    mut clock_text: Query<&mut Text, (With<RaceClockText>, Without<CountdownText>)>,
+) {
+    let elapsed = race.clock.as_ref().map(|clock| clock.elapsed_secs());
+
+    let countdown = match (race.countdown, elapsed) {
+        (Some(count), _) => count.to_string(),
+        (None, Some(elapsed)) if elapsed < GO_TEXT_DURATION => "GO!".to_string(),
+        _ => String::new(),
+    };
+    for mut text in &mut countdown_text {
+        text.sections[0].value = countdown.clone();
+    }
+
+    let clock = elapsed.map(format_time).unwrap_or_default();
+    for mut text in &mut clock_text {
+        text.sections[0].value = clock.clone();
+    }
+}
+
+// shows a number of seconds as m:ss.ss
+pub fn format_time(seconds: f32) -> String {
+    let minutes = (seconds / 60.0).floor();
+    format!("{}:{:05.2}", minutes, seconds - minutes * 60.0)
+}
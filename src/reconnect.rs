@@ -0,0 +1,207 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy_renet::renet::{DefaultChannel, RenetClient};
+
+use crate::{
+    connection_lost::lose_connection,
+    join_menu::{address_client, new_client, BindError, HostnameLookup, LastServer, NewClient},
+    main_menu::HostClient,
+    messages::ClientMessageReliable,
+    settings::Settings,
+    GameState, MultiplayerSetting,
+};
+
+// how many times to try to get back in before giving up
+const MAX_RECONNECT_ATTEMPTS: u32 = 6;
+// the wait before the first try, it doubles after every one that fails
+const FIRST_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+pub struct ReconnectPlugin;
+
+impl Plugin for ReconnectPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(reconnect)
+            .add_system(show_reconnecting.after(reconnect));
+    }
+}
+
+// the connection dropped while playing and we are trying to get it back.
+// the level, maps and player are left alone so the game carries on
+#[derive(Resource)]
+pub struct Reconnecting {
+    // how many clients have been made so far
+    attempts: u32,
+    // counts down to the next one
+    timer: Timer,
+    // the levels finished while there was no connection, in order
+    pub goals: Vec<u8>,
+    // the server's hostname being looked up for the next try
+    lookup: Option<HostnameLookup>,
+}
+
+impl Default for Reconnecting {
+    fn default() -> Self {
+        Reconnecting {
+            attempts: 0,
+            timer: Timer::new(FIRST_RECONNECT_DELAY, TimerMode::Once),
+            goals: Vec::new(),
+            lookup: None,
+        }
+    }
+}
+
+impl Reconnecting {
+    // waits longer before the next try. false if we have tried enough
+    pub fn schedule_next(&mut self) -> bool {
+        if self.attempts >= MAX_RECONNECT_ATTEMPTS {
+            return false;
+        }
+        self.timer = Timer::new(
+            FIRST_RECONNECT_DELAY * 2u32.pow(self.attempts),
+            TimerMode::Once,
+        );
+        true
+    }
+}
+
+// makes a new client for the last server when the timer runs out. it uses the
+// same session so the server gives us back our place
+#[allow(clippy::too_many_arguments)]
+fn reconnect(
+    mut commands: Commands,
+    time: Res<Time>,
+    reconnecting: Option<ResMut<Reconnecting>>,
+    mut client: Option<ResMut<RenetClient>>,
+    last_server: Option<Res<LastServer>>,
+    setting: Res<MultiplayerSetting>,
+    settings: Res<Settings>,
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    let Some(mut reconnecting) = reconnecting else {
+        return;
+    };
+    // they gave up and went back to the menu
+    let (HostClient::Client, Some(last_server)) = (&setting.0, last_server) else {
+        commands.remove_resource::<Reconnecting>();
+        return;
+    };
+
+    if let Some(client) = client.as_mut() {
+        if client.is_connected() {
+            println!("reconnected to the server");
+            commands.remove_resource::<Reconnecting>();
+            // catch the server up on the levels we finished, so it checks
+            // our positions against the right one
+            let goals = reconnecting.goals.iter();
+            let messages = goals
+                .map(|&level| ClientMessageReliable::ReachedGoal { level })
+                // the walls may have moved while we were gone
+                .chain([ClientMessageReliable::RequestWalls]);
+            for message in messages {
+                client.send_message(
+                    DefaultChannel::Reliable,
+                    bincode::serialize(&message).unwrap(),
+                );
+            }
+        }
+        // otherwise wait for it to connect or fail
+        return;
+    }
+
+    // the hostname is looked up on another thread, the network may be too slow to answer
+    if let Some(lookup) = &reconnecting.lookup {
+        let Some(found) = lookup.poll() else {
+            return;
+        };
+        reconnecting.lookup = None;
+        let client = found
+            .map_err(BindError::Address)
+            .and_then(|server_addr| address_client(server_addr, last_server.session, &settings));
+        use_client(&mut commands, &mut reconnecting, &mut game_state, client);
+        return;
+    }
+
+    if !reconnecting.timer.tick(time.delta()).finished() {
+        return;
+    }
+    reconnecting.attempts += 1;
+    println!(
+        "reconnecting, attempt {} of {MAX_RECONNECT_ATTEMPTS}",
+        reconnecting.attempts
+    );
+
+    match new_client(&last_server.target, last_server.session, &settings) {
+        Ok(NewClient::Ready(client)) => commands.insert_resource(*client),
+        Ok(NewClient::LookingUp(lookup)) => reconnecting.lookup = Some(lookup),
+        Err(e) => use_client(&mut commands, &mut reconnecting, &mut game_state, Err(e)),
+    }
+}
+
+// carries on with the new client, or waits to try again if it couldn't be made
+fn use_client(
+    commands: &mut Commands,
+    reconnecting: &mut Reconnecting,
+    game_state: &mut NextState<GameState>,
+    client: Result<RenetClient, BindError>,
+) {
+    match client {
+        Ok(client) => commands.insert_resource(client),
+        // eg the hostname can't be looked up while the network is down
+        Err(e) => {
+            if !reconnecting.schedule_next() {
+                lose_connection(commands, game_state, e.to_string());
+            }
+        }
+    }
+}
+
+#[derive(Component)]
+struct ReconnectingText;
+
+// tells the player why nobody else is moving
+fn show_reconnecting(
+    mut commands: Commands,
+    reconnecting: Option<Res<Reconnecting>>,
+    mut text: Query<(Entity, &mut Text), With<ReconnectingText>>,
+    asset_server: Res<AssetServer>,
+) {
+    let Some(reconnecting) = reconnecting else {
+        for (entity, _) in text.iter() {
+            commands.entity(entity).despawn();
+        }
+        return;
+    };
+
+    let value = format!(
+        "Connection lost, reconnecting (attempt {} of {MAX_RECONNECT_ATTEMPTS})...",
+        reconnecting.attempts.max(1)
+    );
+    if let Ok((_, mut text)) = text.get_single_mut() {
+        if text.sections[0].value != value {
+            text.sections[0].value = value;
+        }
+        return;
+    }
+
+    commands.spawn((
+        TextBundle::from_section(
+            value,
+            TextStyle {
+                font: asset_server.load("fonts/Rubik-SemiBold.ttf"),
+                font_size: 30.0,
+                color: Color::BLACK,
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                top: Val::Px(10.0),
+                left: Val::Px(10.0),
+                ..default()
+            },
+            ..default()
+        }),
+        ReconnectingText,
+    ));
+}
@@ -0,0 +1,543 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::RigidBody;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::config_directory,
+    editor::EditorPlaytest,
+    grappling_hook::{hook_state, Hook, MovingGrappleHook},
+    level::Level,
+    messages::{Facing, HookState},
+    moving_block::MovableWall,
+    platform::{spawn_level, Goal, KillerWall, Maps, Wall},
+    player::Player,
+    race::format_time,
+    startup_plugin::{despawn_everything, GameTextures},
+    toast::Toasts,
+    CurrentLevel, GameState, BACKGROUND_COLOUR, HOOK_SPRITE_SIZE,
+};
+
+// replays are saved in this folder in the config folder
+const REPLAY_FOLDER: &str = "replays";
+const REPLAY_EXTENSION: &str = "replay";
+// bumped when the file changes so old replays are turned away instead of misread
+const REPLAY_VERSION: u16 = 1;
+// a wall has to move this far before its position is recorded again
+const WALL_MOVE_THRESHOLD: f32 = 0.5;
+// how many seconds holding left or right skips every second
+const SCRUB_SPEED: f32 = 10.0;
+// the slowest and fastest a replay can be played
+const MIN_SPEED: f32 = 0.25;
+const MAX_SPEED: f32 = 4.0;
+
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(start_recording.in_schedule(OnEnter(GameState::Gameplay)))
+            .add_system(record_frame.in_set(OnUpdate(GameState::Gameplay)))
+            .add_system(save_recording.in_schedule(OnEnter(GameState::Win)))
+            // leaving part of the way through a run throws it away
+            .add_system(stop_recording.in_schedule(OnEnter(GameState::Menu)))
+            .add_system(stop_recording.in_schedule(OnEnter(GameState::Lobby)))
+            .add_system(setup_replay.in_schedule(OnEnter(GameState::Replay)))
+            .add_system(replay_input.in_set(OnUpdate(GameState::Replay)))
+            .add_system(freeze_walls.in_set(OnUpdate(GameState::Replay)))
+            .add_system(
+                play_replay
+                    .after(replay_input)
+                    .after(freeze_walls)
+                    .in_set(OnUpdate(GameState::Replay)),
+            )
+            .add_system(
+                update_replay_text
+                    .after(replay_input)
+                    .in_set(OnUpdate(GameState::Replay)),
+            )
+            .add_system(despawn_everything.in_schedule(OnExit(GameState::Replay)))
+            .add_system(end_replay.in_schedule(OnExit(GameState::Replay)));
+    }
+}
+
+// where everything was on one frame of the run
+#[derive(Serialize, Deserialize)]
+struct Frame {
+    // seconds of gameplay since the run started. the death and next
+    // level screens aren't counted so the replay doesn't stop for them
+    time: f32,
+    level: u8,
+    // the level was spawned (again, after dying) on this frame
+    spawned: bool,
+    position: Vec2,
+    facing: Facing,
+    hook: HookState,
+    // the movable walls that moved since the last frame, by wall id
+    walls: Vec<(i32, Vec2)>,
+}
+
+// a whole run from level 1 to the win screen. the levels are kept with it
+// so it can be watched on another computer or after the levels change
+#[derive(Serialize, Deserialize)]
+struct Replay {
+    // has to stay first so it can be read on its own
+    version: u16,
+    levels: Vec<(u8, Level)>,
+    frames: Vec<Frame>,
+}
+
+// records the run being played
+#[derive(Resource)]
+struct Recorder {
+    replay: Replay,
+    // seconds of gameplay so far
+    time: f32,
+    // the next frame is the first one on a freshly spawned level
+    spawned: bool,
+    // where each wall was last recorded
+    walls: HashMap<i32, Vec2>,
+}
+
+// starts a recording at the beginning of a run, and marks where the level
+// was spawned again on every level after that
+fn start_recording(
+    mut commands: Commands,
+    recorder: Option<ResMut<Recorder>>,
+    maps: Res<Maps>,
+    playtest: Res<EditorPlaytest>,
+) {
+    // play-testing in the editor isn't a run
+    if playtest.0 {
+        return;
+    }
+
+    if let Some(mut recorder) = recorder {
+        recorder.spawned = true;
+        recorder.walls.clear();
+        return;
+    }
+
+    let mut levels: Vec<(u8, Level)> = maps
+        .maps
+        .iter()
+        .map(|(number, level)| (*number, level.clone()))
+        .collect();
+    levels.sort_by_key(|(number, _)| *number);
+
+    commands.insert_resource(Recorder {
+        replay: Replay {
+            version: REPLAY_VERSION,
+            levels,
+            frames: vec![],
+        },
+        time: 0.0,
+        spawned: true,
+        walls: HashMap::new(),
+    });
+}
+
+fn record_frame(
+    recorder: Option<ResMut<Recorder>>,
+    time: Res<Time>,
+    level: Res<CurrentLevel>,
+    player: Query<(&Transform, &Player)>,
+    hooks: Query<(&Transform, Option<&MovingGrappleHook>), (With<Hook>, Without<Player>)>,
+    walls: Query<(&Transform, &MovableWall)>,
+) {
+    let Some(mut recorder) = recorder else {
+        return;
+    };
+    // the level hasn't been spawned yet
+    let Ok((transform, player)) = player.get_single() else {
+        return;
+    };
+    recorder.time += time.delta_seconds();
+
+    // only the walls that have moved are kept, most of them never do
+    let mut moved = vec![];
+    for (wall_transform, wall) in walls.iter() {
+        let position = wall_transform.translation.truncate();
+        if let Some(last) = recorder.walls.get(&wall.unique_id) {
+            if last.distance(position) <= WALL_MOVE_THRESHOLD {
+                continue;
+            }
+        }
+        recorder.walls.insert(wall.unique_id, position);
+        moved.push((wall.unique_id, position));
+    }
+
+    let frame = Frame {
+        time: recorder.time,
+        level: level.level_number,
+        spawned: std::mem::take(&mut recorder.spawned),
+        position: transform.translation.truncate(),
+        facing: player.facing,
+        hook: hook_state(hooks.get_single().ok()),
+        walls: moved,
+    };
+    recorder.replay.frames.push(frame);
+}
+
+// the run is over, save it so it can be watched
+fn save_recording(
+    mut commands: Commands,
+    recorder: Option<Res<Recorder>>,
+    mut toasts: ResMut<Toasts>,
+) {
+    let Some(recorder) = recorder else {
+        return;
+    };
+    commands.remove_resource::<Recorder>();
+
+    match save_replay(&recorder.replay) {
+        Ok(path) => toasts.push(format!("saved a replay to {}", path.display())),
+        Err(e) => toasts.push(e),
+    }
+}
+
+fn stop_recording(mut commands: Commands) {
+    commands.remove_resource::<Recorder>();
+}
+
+fn replay_directory() -> PathBuf {
+    config_directory().join(REPLAY_FOLDER)
+}
+
+fn save_replay(replay: &Replay) -> Result<PathBuf, String> {
+    let directory = replay_directory();
+    fs::create_dir_all(&directory)
+        .map_err(|e| format!("could not create {}: {e}", directory.display()))?;
+
+    let seconds = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let path = directory.join(format!("replay-{seconds}.{REPLAY_EXTENSION}"));
+    fs::write(&path, bincode::serialize(replay).unwrap())
+        .map_err(|e| format!("could not save the replay to {}: {e}", path.display()))?;
+    Ok(path)
+}
+
+// the replay that was saved, or put in the folder, most recently
+fn newest_replay() -> Option<PathBuf> {
+    fs::read_dir(replay_directory())
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .path()
+                .extension()
+                .is_some_and(|ext| ext == REPLAY_EXTENSION)
+        })
+        .max_by_key(|entry| entry.metadata().and_then(|data| data.modified()).ok())
+        .map(|entry| entry.path())
+}
+
+fn load_replay(path: &Path) -> Result<Replay, String> {
+    let bytes = fs::read(path).map_err(|e| format!("could not read {}: {e}", path.display()))?;
+    let unreadable = || {
+        format!(
+            "{} can't be played by this version of the game",
+            path.display()
+        )
+    };
+
+    // check the version first, an old replay might happen to decode as garbage
+    if bincode::deserialize::<u16>(&bytes).ok() != Some(REPLAY_VERSION) {
+        return Err(unreadable());
+    }
+    let replay: Replay = bincode::deserialize(&bytes).map_err(|_| unreadable())?;
+    if replay.frames.is_empty() {
+        return Err(format!("{} is empty", path.display()));
+    }
+    Ok(replay)
+}
+
+// loads the newest replay to be watched in GameState::Replay
+pub fn start_replay(commands: &mut Commands) -> Result<(), String> {
+    let path = newest_replay().ok_or_else(|| {
+        format!(
+            "there are no replays in {} yet, finish a run to record one",
+            replay_directory().display()
+        )
+    })?;
+    let replay = load_replay(&path)?;
+    println!("playing {}", path.display());
+
+    commands.insert_resource(Playback {
+        replay,
+        time: 0.0,
+        speed: 1.0,
+        paused: false,
+        segment: None,
+        wall_starts: HashMap::new(),
+    });
+    Ok(())
+}
+
+// the replay being watched
+#[derive(Resource)]
+struct Playback {
+    replay: Replay,
+    // how far through it is, in seconds
+    time: f32,
+    speed: f32,
+    paused: bool,
+    // the frame the spawned level was spawned on, None before anything is spawned
+    segment: Option<usize>,
+    // where the movable walls of the spawned level started, by wall id
+    wall_starts: HashMap<i32, Vec2>,
+}
+
+impl Playback {
+    fn duration(&self) -> f32 {
+        self.replay.frames.last().map_or(0.0, |frame| frame.time)
+    }
+
+    // the last frame before the time, there is always one
+    fn frame_index(&self) -> usize {
+        self.replay
+            .frames
+            .partition_point(|frame| frame.time <= self.time)
+            .saturating_sub(1)
+    }
+}
+
+#[derive(Component)]
+struct ReplayCamera;
+
+#[derive(Component)]
+struct ReplayHook;
+
+#[derive(Component)]
+struct ReplayText;
+
+fn setup_replay(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(ClearColor(BACKGROUND_COLOUR));
+    commands.spawn((Camera2dBundle::default(), ReplayCamera));
+
+    let style = TextStyle {
+        font: asset_server.load("fonts/Rubik-SemiBold.ttf"),
+        font_size: 30.0,
+        color: Color::BLACK,
+    };
+    commands.spawn((
+        TextBundle::from_sections([
+            TextSection::new("", style.clone()),
+            TextSection::new(
+                "Space: pause   Left/Right: scrub   Up/Down: speed   R: restart   Escape: menu",
+                TextStyle {
+                    font_size: 20.0,
+                    ..style
+                },
+            ),
+        ])
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                top: Val::Px(10.0),
+                left: Val::Px(10.0),
+                ..default()
+            },
+            ..default()
+        }),
+        ReplayText,
+    ));
+}
+
+fn replay_input(keys: Res<Input<KeyCode>>, time: Res<Time>, mut playback: ResMut<Playback>) {
+    let duration = playback.duration();
+
+    if keys.just_pressed(KeyCode::Space) {
+        // pressing play at the end starts it again
+        if !playback.paused && playback.time >= duration {
+            playback.time = 0.0;
+        } else {
+            playback.paused = !playback.paused;
+        }
+    }
+    if keys.just_pressed(KeyCode::R) {
+        playback.time = 0.0;
+    }
+    if keys.just_pressed(KeyCode::Up) {
+        playback.speed = (playback.speed * 2.0).min(MAX_SPEED);
+    }
+    if keys.just_pressed(KeyCode::Down) {
+        playback.speed = (playback.speed / 2.0).max(MIN_SPEED);
+    }
+
+    let mut scrub = 0.0;
+    if keys.pressed(KeyCode::Left) {
+        scrub -= SCRUB_SPEED;
+    }
+    if keys.pressed(KeyCode::Right) {
+        scrub += SCRUB_SPEED;
+    }
+
+    // scrubbing works while paused
+    let step = if scrub != 0.0 {
+        scrub
+    } else if playback.paused {
+        0.0
+    } else {
+        playback.speed
+    };
+    playback.time = (playback.time + step * time.delta_seconds()).clamp(0.0, duration);
+}
+
+// the walls are moved by the replay, not the physics
+fn freeze_walls(
+    mut walls: Query<(&mut RigidBody, &Transform, &MovableWall), Added<MovableWall>>,
+    mut playback: ResMut<Playback>,
+) {
+    for (mut body, transform, wall) in walls.iter_mut() {
+        *body = RigidBody::KinematicPositionBased;
+        playback
+            .wall_starts
+            .insert(wall.unique_id, transform.translation.truncate());
+    }
+}
+
+// puts everything where it was at the replay's time. the level is spawned
+// again whenever the replay moves into a different part of the run
+#[allow(clippy::too_many_arguments)]
+fn play_replay(
+    mut commands: Commands,
+    mut playback: ResMut<Playback>,
+    game_textures: Res<GameTextures>,
+    level_entities: Query<
+        Entity,
+        Or<(
+            With<Wall>,
+            With<KillerWall>,
+            With<Goal>,
+            With<MovableWall>,
+            With<Player>,
+        )>,
+    >,
+    mut player: Query<(&mut Transform, &mut Sprite), With<Player>>,
+    mut walls: Query<(&mut Transform, &MovableWall), Without<Player>>,
+    mut hook: Query<
+        (Entity, &mut Transform),
+        (With<ReplayHook>, Without<Player>, Without<MovableWall>),
+    >,
+    mut camera: Query<
+        &mut Transform,
+        (
+            With<ReplayCamera>,
+            Without<Player>,
+            Without<MovableWall>,
+            Without<ReplayHook>,
+        ),
+    >,
+) {
+    let index = playback.frame_index();
+    let frames = &playback.replay.frames;
+    let segment = frames[..=index]
+        .iter()
+        .rposition(|frame| frame.spawned)
+        .unwrap_or(0);
+
+    if playback.segment != Some(segment) {
+        for entity in level_entities.iter() {
+            commands.entity(entity).despawn();
+        }
+        let number = frames[segment].level;
+        if let Some((_, level)) = playback.replay.levels.iter().find(|(n, _)| *n == number) {
+            spawn_level(&mut commands, &game_textures, level, number);
+        }
+        playback.wall_starts.clear();
+        playback.segment = Some(segment);
+        // the level is there to move around next frame
+        return;
+    }
+
+    let frame = &frames[index];
+    if let Ok((mut transform, mut sprite)) = player.get_single_mut() {
+        transform.translation.x = frame.position.x;
+        transform.translation.y = frame.position.y;
+        sprite.flip_x = frame.facing == Facing::Left;
+    }
+    if let Ok(mut camera) = camera.get_single_mut() {
+        camera.translation.x = frame.position.x;
+        camera.translation.y = frame.position.y;
+    }
+
+    // where every wall is by now, going back to the start works the same as forwards
+    let mut positions = playback.wall_starts.clone();
+    for earlier in &frames[segment..=index] {
+        positions.extend(earlier.walls.iter().copied());
+    }
+    for (mut transform, wall) in walls.iter_mut() {
+        if let Some(position) = positions.get(&wall.unique_id) {
+            transform.translation.x = position.x;
+            transform.translation.y = position.y;
+        }
+    }
+
+    // the hook points the way it is going, or away from the player once it has hit
+    let shown = match frame.hook {
+        HookState::None => None,
+        HookState::Flying {
+            position,
+            direction,
+        } => Some((position, direction)),
+        HookState::Attached { anchor } => Some((anchor, anchor - frame.position)),
+    };
+    let hook_transform = |(position, direction): (Vec2, Vec2)| Transform {
+        translation: position.extend(11.0),
+        rotation: Quat::from_rotation_z(Vec2::Y.angle_between(direction)),
+        ..default()
+    };
+    match (shown, hook.get_single_mut()) {
+        (Some(shown), Ok((_, mut transform))) => *transform = hook_transform(shown),
+        (Some(shown), Err(_)) => {
+            commands.spawn((
+                SpriteBundle {
+                    texture: game_textures.hook.clone(),
+                    sprite: Sprite {
+                        custom_size: Some(HOOK_SPRITE_SIZE),
+                        ..default()
+                    },
+                    transform: hook_transform(shown),
+                    ..default()
+                },
+                ReplayHook,
+            ));
+        }
+        (None, Ok((entity, _))) => commands.entity(entity).despawn(),
+        (None, Err(_)) => (),
+    }
+}
+
+fn update_replay_text(playback: Res<Playback>, mut text: Query<&mut Text, With<ReplayText>>) {
+    let frame = &playback.replay.frames[playback.frame_index()];
+    let duration = playback.duration();
+    let state = if playback.paused {
+        "paused"
+    } else if playback.time >= duration {
+        "finished"
+    } else {
+        ""
+    };
+
+    for mut text in &mut text {
+        text.sections[0].value = format!(
+            "Level {}   {} / {}   x{}   {state}\n",
+            frame.level,
+            format_time(playback.time),
+            format_time(duration),
+            playback.speed
+        );
+    }
+}
+
+fn end_replay(mut commands: Commands) {
+    commands.remove_resource::<Playback>();
+}
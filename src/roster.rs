@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::{
+    client::UserIdMap,
+    messages::{RosterPlayer, ServerMessageReliable},
+    moving_block::BlockMap,
+    toast::Toasts,
+};
+
+pub struct RosterPlugin;
+
+impl Plugin for RosterPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Roster>()
+            .add_event::<RosterEvent>()
+            .add_system(apply_roster_events);
+    }
+}
+
+// the name of everyone on the server we are connected to, the key is their id.
+// the server sends all of it when we join and then who comes and goes
+#[derive(Resource, Default)]
+pub struct Roster(pub HashMap<u64, String>);
+
+pub enum RosterEvent {
+    Everyone(Vec<RosterPlayer>),
+    Joined { id: u64, name: String },
+    Left { id: u64 },
+}
+
+// the roster messages can arrive in any state, so whatever system is reading
+// the server's messages passes them on with this
+pub fn roster_event(message: &ServerMessageReliable) -> Option<RosterEvent> {
+    match message {
+        ServerMessageReliable::Roster(players) => Some(RosterEvent::Everyone(players.clone())),
+        ServerMessageReliable::PlayerConnected { id, name } => Some(RosterEvent::Joined {
+            id: *id,
+            name: name.clone(),
+        }),
+        ServerMessageReliable::PlayerDisconnected { id } => Some(RosterEvent::Left { id: *id }),
+        _ => None,
+    }
+}
+
+fn apply_roster_events(
+    mut events: EventReader<RosterEvent>,
+    mut roster: ResMut<Roster>,
+    mut toasts: ResMut<Toasts>,
+    mut player_map: ResMut<UserIdMap>,
+    mut block_map: ResMut<BlockMap>,
+) {
+    for event in events.iter() {
+        match event {
+            RosterEvent::Everyone(players) => {
+                roster.0 = players
+                    .iter()
+                    .map(|player| (player.id, player.name.clone()))
+                    .collect();
+            }
+            RosterEvent::Joined { id, name } => {
+                toasts.push(format!("{name} joined"));
+                roster.0.insert(*id, name.clone());
+            }
+            RosterEvent::Left { id } => {
+                let name = roster
+                    .0
+                    .remove(id)
+                    .unwrap_or_else(|| format!("Player {}", id % 1000));
+                toasts.push(format!("{name} left"));
+
+                // update_players despawns them once they are gone from the map
+                player_map.0.remove(id);
+                // the walls they were holding can be moved by anyone
+                for wall in block_map.blocks.values_mut() {
+                    if wall.owner == Some(*id) {
+                        wall.owner = None;
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,17 +1,27 @@
-use crate::{MultiplayerSetting, main_menu::HostClient};
+use crate::{main_menu::HostClient, race::Race, MultiplayerSetting};
 
 use bevy::prelude::*;
+use bevy_renet::renet::RenetClient;
 
-pub fn run_if_online(host: Res<MultiplayerSetting>) -> bool {
-    !matches!(host.0, HostClient::Play)
+// there is no client while reconnecting, so these wait until there is one
+pub fn run_if_online(host: Res<MultiplayerSetting>, client: Option<Res<RenetClient>>) -> bool {
+    !matches!(host.0, HostClient::Play) && client.is_some()
 }
 
 // for systems that run if we are in client mode
-pub fn run_if_client(host_or_join: Res<MultiplayerSetting>) -> bool {
-    matches!(host_or_join.0, HostClient::Client | HostClient::Host)
+pub fn run_if_client(
+    host_or_join: Res<MultiplayerSetting>,
+    client: Option<Res<RenetClient>>,
+) -> bool {
+    matches!(host_or_join.0, HostClient::Client | HostClient::Host) && client.is_some()
 }
 
 // allows systems to run if the host setting is on
 pub fn run_if_host(host: Res<MultiplayerSetting>) -> bool {
     matches!(host.0, HostClient::Host)
-}
\ No newline at end of file
+}
+
+// stops the player moving while a race is counting down
+pub fn run_if_not_waiting_for_race(race: Res<Race>) -> bool {
+    !race.waiting
+}
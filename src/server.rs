@@ -1,86 +1,335 @@
 use std::{
+    collections::VecDeque,
     net::{IpAddr, SocketAddr, UdpSocket},
-    time::SystemTime,
+    time::{Duration, SystemTime},
 };
 
-use bevy::prelude::*;
+use bevy::{prelude::*, utils::HashMap};
 use bevy_renet::{
     renet::{
-        DefaultChannel, RenetConnectionConfig, RenetError, RenetServer, ServerAuthentication,
-        ServerConfig, ServerEvent,
+        DefaultChannel, RenetConnectionConfig, RenetServer, ServerConfig, ServerEvent,
     },
     RenetServerPlugin,
 };
-use local_ip_address::local_ip;
 
 use crate::{
-    client::PROTOCOL_ID,
+    anti_cheat::{MovementCheck, Verdict},
+    auth::{server_authentication, PrivateKey},
+    discovery::answer_discovery,
+    level::level_hash,
     messages::{
-        ClientMessageReliable, ClientMessageUnreliable, ServerMessageReliable,
-        ServerMessageUnreliable,
+        clean_chat, decode, name_from_user_data, ClientMessageReliable, ClientMessageUnreliable,
+        GameMode, LobbyPlayer, MapInfo, RosterPlayer, ServerMessageReliable,
+        ServerMessageUnreliable, Standing, GAME_VERSION, MAP_CHUNK_SIZE, PROTOCOL_ID,
+        PROTOCOL_VERSION,
     },
-    platform::Maps, run_if::run_if_host,
+    moving_block::SharedWall,
+    platform::Maps,
+    run_if::run_if_host,
+    settings::Settings,
 };
 
 // the default ports for the client and server
 pub const SERVER_PORT: u16 = 42069;
 pub const CLIENT_PORT: u16 = 5001;
 
+// the most players that can be connected at once
+pub const MAX_CLIENTS: usize = 64;
+
+// a client that sends this many packets we can't read is kicked
+const MAX_MALFORMED_PACKETS: u32 = 20;
+// how long a rejected client has to read why before it is disconnected
+const REJECT_DELAY: Duration = Duration::from_secs(1);
+// how many map chunks are sent to each client every update. sending a whole
+// map pack at once would overflow renet's reliable channel
+const MAP_CHUNKS_PER_TICK: usize = 4;
+// how many seconds the countdown before a race lasts
+const RACE_COUNTDOWN: u8 = 3;
+// how long a client that dropped out has to come back and carry on where it was
+const SESSION_TIMEOUT: f64 = 120.0;
+// a client can send this many chat messages every CHAT_WINDOW seconds
+const CHAT_LIMIT: usize = 5;
+const CHAT_WINDOW: f64 = 10.0;
+
 pub struct MyServerPlugin;
 
 impl Plugin for MyServerPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugin(RenetServerPlugin::default())
-            .add_system(panic_on_error_system.run_if(run_if_host))
-            .add_system(server_update_system.run_if(run_if_host));
+            .insert_resource(ServerWalls(HashMap::new()))
+            .insert_resource(ServerClients(HashMap::new()))
+            .init_resource::<DepartedClients>()
+            // init so the dedicated server can choose the game mode first
+            .init_resource::<ServerLobby>()
+            .insert_resource(ServerRace::default())
+            .add_system(server_update_system.run_if(run_if_host))
+            .add_system(kick_rejected_clients.run_if(run_if_host))
+            .add_system(send_map_chunks.run_if(run_if_host))
+            .add_system(race_countdown.run_if(run_if_host))
+            .add_system(answer_discovery.run_if(run_if_host));
+    }
+}
+
+// what the server knows about a connected client
+#[derive(Default)]
+pub struct ConnectedClient {
+    // the display name from the client's user data
+    pub name: String,
+    // has sent a hello with a protocol version we can talk to
+    pub verified: bool,
+    // has downloaded the maps and is in the lobby (or playing)
+    pub in_lobby: bool,
+    pub ready: bool,
+    // how many packets from this client couldn't be decoded
+    pub malformed_packets: u32,
+    // checks the positions and levels it sends are possible
+    movement: MovementCheck,
+    // counts down after the client was rejected, it is kicked when it finishes
+    kick_timer: Option<Timer>,
+    // it was kicked for misbehaving, so it doesn't get its place back
+    kicked: bool,
+    // MapChunk messages (already serialised) waiting to be sent
+    map_chunks: VecDeque<Vec<u8>>,
+    // when its last few chat messages were sent (the server's clock)
+    chat_times: VecDeque<f64>,
+}
+
+impl ConnectedClient {
+    // whether it can send another chat message now, and counts it if it can
+    fn can_chat(&mut self, now: f64) -> bool {
+        // forget the messages that have left the window
+        self.chat_times.retain(|sent| now - sent < CHAT_WINDOW);
+        if self.chat_times.len() >= CHAT_LIMIT {
+            return false;
+        }
+        self.chat_times.push_back(now);
+        true
+    }
+}
+
+// every connected client, the key is the client id
+#[derive(Resource)]
+pub struct ServerClients(pub HashMap<u64, ConnectedClient>);
+
+// clients that lost their connection, with when they went (the server's clock).
+// a client that connects again with the same id (its session) gets its place back
+#[derive(Resource, Default)]
+pub struct DepartedClients(HashMap<u64, (ConnectedClient, f64)>);
+
+impl ServerClients {
+    fn is_verified(&self, client_id: u64) -> bool {
+        self.0.get(&client_id).is_some_and(|c| c.verified)
+    }
+
+    // counts a packet that couldn't be decoded, kicks the client if it keeps happening
+    fn malformed_packet(&mut self, client_id: u64, server: &mut RenetServer) {
+        let client = self.0.entry(client_id).or_default();
+        client.malformed_packets += 1;
+        println!(
+            "malformed packet {} from client {client_id}",
+            client.malformed_packets
+        );
+
+        if client.malformed_packets >= MAX_MALFORMED_PACKETS {
+            println!("kicking client {client_id} for sending too many malformed packets");
+            client.kicked = true;
+            server.disconnect(client_id);
+        }
+    }
+
+    // counts a move that can't have happened, kicks the client if it keeps happening
+    fn bad_move(&mut self, client_id: u64, reason: &str, now: f64, server: &mut RenetServer) {
+        let client = self.0.entry(client_id).or_default();
+        println!("client {client_id} {reason}");
+
+        if client.movement.violation(now) && client.kick_timer.is_none() {
+            println!("kicking client {client_id} for moving in ways the game doesn't allow");
+            client.kicked = true;
+            // give the message time to arrive before kicking them
+            client.kick_timer = Some(Timer::new(REJECT_DELAY, TimerMode::Once));
+            let message = ServerMessageReliable::Rejected {
+                reason: "You were kicked for moving in ways the game doesn't allow".to_string(),
+            };
+            server.send_message(
+                client_id,
+                DefaultChannel::Reliable,
+                bincode::serialize(&message).unwrap(),
+            );
+        }
+    }
+}
+
+// the lobby everyone waits in before the game starts
+#[derive(Resource, Default)]
+pub struct ServerLobby {
+    // the client playing on the same machine as the server, it decides when to start.
+    // a dedicated server has no host and starts when everyone is ready
+    pub host: Option<u64>,
+    // once the game has started, people that join go straight to level 1
+    pub started: bool,
+    pub mode: GameMode,
+}
+
+impl ServerLobby {
+    // everyone in the lobby apart from the host has to be ready
+    fn everyone_ready(&self, clients: &ServerClients) -> bool {
+        let mut players = clients.0.iter().filter(|(_, c)| c.in_lobby).peekable();
+        players.peek().is_some()
+            && players.all(|(id, client)| client.ready || Some(*id) == self.host)
+    }
+}
+
+// the times of the current race
+#[derive(Resource, Default)]
+pub struct ServerRace {
+    // counts down to the start of the race
+    countdown: Option<Timer>,
+    // the number that was last sent to the clients
+    last_count: u8,
+    // when the race started (seconds since the server started)
+    start: Option<f64>,
+    // the name and splits of everyone that is racing
+    results: HashMap<u64, (String, Vec<f32>)>,
+    // the clients that finished every level, first place first
+    finish_order: Vec<u64>,
+}
+
+impl ServerRace {
+    // everyone's results sorted from first to last. finished players are ordered
+    // by when they finished, the rest by how far they got and then how fast
+    fn standings(&self) -> Vec<Standing> {
+        let mut standings: Vec<Standing> = self
+            .results
+            .iter()
+            .map(|(id, (name, splits))| Standing {
+                id: *id,
+                name: name.clone(),
+                splits: splits.clone(),
+                finished: self.finish_order.contains(id),
+            })
+            .collect();
+
+        let place = |id: &u64| self.finish_order.iter().position(|f| f == id);
+        standings.sort_by(|a, b| match (place(&a.id), place(&b.id)) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (None, None) => b.splits.len().cmp(&a.splits.len()).then_with(|| {
+                let a = a.splits.last().copied().unwrap_or(0.0);
+                let b = b.splits.last().copied().unwrap_or(0.0);
+                a.total_cmp(&b)
+            }),
+        });
+        standings
     }
 }
 
+// the server's copy of every movable wall, the key is (level, wall id).
+// this is the real position, clients copy it
+#[derive(Resource)]
+pub struct ServerWalls(pub HashMap<(u8, i32), SharedWall>);
 
+// the server the host button starts. public_ip is the address players type in,
+// it depends on the host mode in the settings. with a private key only players
+// with a token made from it can join
+pub fn new_renet_server(
+    public_ip: IpAddr,
+    settings: &Settings,
+    private_key: Option<PrivateKey>,
+) -> std::io::Result<RenetServer> {
+    // sets up the binding to the address in the settings (every network by default)
+    let inbound_server_addr = SocketAddr::new(settings.bind_address, settings.server_port);
+
+    // the address clients connect to. for public hosting this needs port forwarding on your router
+    let server_addr = SocketAddr::new(public_ip, settings.server_port);
+
+    bind_renet_server(inbound_server_addr, server_addr, private_key)
+}
 
-pub fn new_renet_server(public_ip: IpAddr) -> RenetServer {
-    // sets up the binding to the public ip address
-    let inbound_server_addr = SocketAddr::new(local_ip().unwrap(), SERVER_PORT);
-    let socket = UdpSocket::bind(inbound_server_addr).unwrap();
+// binds a server to any address. the public address is the one that clients
+// type in to connect, it can be different to the bind address (eg 0.0.0.0)
+pub fn bind_renet_server(
+    bind_addr: SocketAddr,
+    public_addr: SocketAddr,
+    private_key: Option<PrivateKey>,
+) -> std::io::Result<RenetServer> {
+    let socket = UdpSocket::bind(bind_addr)?;
 
-    // Public hosting, requires port forwarding on your router
-    let server_addr = SocketAddr::new(public_ip, SERVER_PORT);
     // sets up the server
     let connection_config = RenetConnectionConfig::default();
-    let server_config =
-        ServerConfig::new(64, PROTOCOL_ID, server_addr, ServerAuthentication::Unsecure);
+    let server_config = ServerConfig::new(
+        MAX_CLIENTS,
+        PROTOCOL_ID,
+        public_addr,
+        server_authentication(private_key),
+    );
     let current_time = SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
         .unwrap();
     // returns the server
-    RenetServer::new(current_time, server_config, connection_config, socket).unwrap()
+    RenetServer::new(current_time, server_config, connection_config, socket)
 }
 
-fn panic_on_error_system(mut renet_error: EventReader<RenetError>) {
-    // if there is an error, it crashes and prints the error
-    // this is for development purposes
-    // I have not seen a crash in the final version
-    for e in renet_error.iter() {
-        panic!("{}", e);
-    }
-}
-
-fn server_update_system(mut server: ResMut<RenetServer>, maps: Res<Maps>) {
+#[allow(clippy::too_many_arguments)]
+fn server_update_system(
+    mut server: ResMut<RenetServer>,
+    maps: Res<Maps>,
+    mut walls: ResMut<ServerWalls>,
+    mut clients: ResMut<ServerClients>,
+    mut lobby: ResMut<ServerLobby>,
+    mut race: ResMut<ServerRace>,
+    mut departed: ResMut<DepartedClients>,
+    time: Res<Time>,
+) {
     for client_id in server.clients_id().into_iter() {
         while let Some(message) = server.receive_message(client_id, DefaultChannel::Unreliable) {
             // recieve all the messages
 
             // deserialise the messages to be understood (they are sent as bytes and then parsed back to data structures)
-            let client_message: ClientMessageUnreliable = bincode::deserialize(&message).unwrap();
+            let Some(client_message) = decode::<ClientMessageUnreliable>(&message) else {
+                clients.malformed_packet(client_id, &mut server);
+                continue;
+            };
+
+            // ignore clients that haven't said hello yet
+            if !clients.is_verified(client_id) {
+                continue;
+            }
 
             // find out what type of message it is
             match client_message {
-                ClientMessageUnreliable::PlayerPosition { level, pos } => {
+                ClientMessageUnreliable::PlayerPosition {
+                    level,
+                    pos,
+                    time: sent_time,
+                    velocity,
+                    facing,
+                    hook,
+                } => {
+                    // only pass on moves the game allows
+                    let now = time.elapsed_seconds_f64();
+                    let client = clients.0.entry(client_id).or_default();
+                    match client
+                        .movement
+                        .check_position(&maps, level, pos.truncate(), now)
+                    {
+                        Verdict::Accept => (),
+                        Verdict::Ignore => continue,
+                        Verdict::Reject(reason) => {
+                            clients.bad_move(client_id, &reason, now, &mut server);
+                            continue;
+                        }
+                    }
+
                     // send the position to all clients except the one that told us
                     let message = ServerMessageUnreliable::PlayerPosition {
                         id: client_id,
                         position: pos,
                         level,
+                        time: sent_time,
+                        velocity,
+                        facing,
+                        hook,
                     };
                     // broadcasts a message to all clients except one
                     server.broadcast_message_except(
@@ -93,16 +342,24 @@ fn server_update_system(mut server: ResMut<RenetServer>, maps: Res<Maps>) {
                 }
 
                 // when the wall position message is recieved by the server, the server
-                // broadcasts a message to all of the other clients, with cliend_id,
+                // broadcasts a message to all of the other clients, with
                 // level, position and wall_id.
                 ClientMessageUnreliable::WallPos {
                     level,
                     wall_id,
                     pos,
                 } => {
+                    // only the client that owns the wall is allowed to move it
+                    let Some(wall) = walls.0.get_mut(&(level, wall_id)) else {
+                        continue;
+                    };
+                    if wall.owner != Some(client_id) {
+                        continue;
+                    }
+                    wall.pos = Some(pos);
+
                     // send the wall positions to all clients except the one that sent it to us
                     let message = ServerMessageUnreliable::WallPos {
-                        client_id,
                         pos,
                         wall_id,
                         level,
@@ -117,11 +374,89 @@ fn server_update_system(mut server: ResMut<RenetServer>, maps: Res<Maps>) {
         }
 
         while let Some(message) = server.receive_message(client_id, DefaultChannel::Reliable) {
-            let client_message: ClientMessageReliable = bincode::deserialize(&message).unwrap();
+            let Some(client_message) = decode::<ClientMessageReliable>(&message) else {
+                clients.malformed_packet(client_id, &mut server);
+                continue;
+            };
+
+            // the hello has to come before anything else
+            if let ClientMessageReliable::Hello {
+                protocol,
+                game_version,
+            } = client_message
+            {
+                let client = clients.0.entry(client_id).or_default();
+                if client.verified || client.kick_timer.is_some() {
+                    continue;
+                }
+
+                let message = if PROTOCOL_VERSION.compatible_with(&protocol) {
+                    println!("client {client_id} is running {game_version} (protocol {protocol})");
+                    client.verified = true;
+                    ServerMessageReliable::Welcome
+                } else {
+                    println!(
+                        "rejecting client {client_id}: protocol {protocol} (game {game_version})"
+                    );
+                    // give the message time to arrive before kicking them
+                    client.kick_timer = Some(Timer::new(REJECT_DELAY, TimerMode::Once));
+                    ServerMessageReliable::Rejected {
+                        reason: format!(
+                            "The server is on version {GAME_VERSION} (protocol \
+                             {PROTOCOL_VERSION}), you have {game_version} (protocol {protocol})"
+                        ),
+                    }
+                };
+                server.send_message(
+                    client_id,
+                    DefaultChannel::Reliable,
+                    bincode::serialize(&message).unwrap(),
+                );
+                if clients.is_verified(client_id) {
+                    send_roster(&mut server, &clients, client_id);
+                }
+                continue;
+            }
+
+            if !clients.is_verified(client_id) {
+                println!("ignoring a message from client {client_id}, it hasn't said hello");
+                continue;
+            }
 
             match client_message {
-                ClientMessageReliable::DebugMessage(string) => {
-                    println!("server recieved message: {}", string)
+                // handled above
+                ClientMessageReliable::Hello { .. } => (),
+
+                // pass it on to everyone (including who sent it) with their name
+                ClientMessageReliable::Chat(text) => {
+                    let text = clean_chat(&text);
+                    if text.is_empty() {
+                        continue;
+                    }
+                    let client = clients.0.entry(client_id).or_default();
+                    if !client.can_chat(time.elapsed_seconds_f64()) {
+                        let message = ServerMessageReliable::Chat {
+                            id: None,
+                            name: String::new(),
+                            text: "You are sending messages too fast".to_string(),
+                            time: unix_time(),
+                        };
+                        server.send_message(
+                            client_id,
+                            DefaultChannel::Reliable,
+                            bincode::serialize(&message).unwrap(),
+                        );
+                        continue;
+                    }
+
+                    println!("{}: {text}", client.name);
+                    let message = ServerMessageReliable::Chat {
+                        id: Some(client_id),
+                        name: client.name.clone(),
+                        text,
+                        time: unix_time(),
+                    };
+                    send_to_verified(&mut server, &clients, &message);
                 }
 
                 ClientMessageReliable::Ping => {
@@ -136,23 +471,210 @@ fn server_update_system(mut server: ResMut<RenetServer>, maps: Res<Maps>) {
                         bincode::serialize(&message).unwrap(),
                     );
 
-                    // send the number of maps
-                    let message = ServerMessageReliable::NumberOfMaps(maps.maps.len() as u16);
+                    // tell the client which maps we have so it can ask for the ones it needs
+                    let list = maps
+                        .maps
+                        .iter()
+                        .map(|(number, map)| MapInfo {
+                            number: *number,
+                            hash: level_hash(map),
+                            size: bincode::serialize(map).unwrap().len() as u32,
+                        })
+                        .collect();
+                    let message = ServerMessageReliable::MapList(list);
                     server.send_message(
                         client_id,
                         DefaultChannel::Reliable,
                         bincode::serialize(&message).unwrap(),
                     );
+                }
+
+                ClientMessageReliable::RequestMap { number } => {
+                    let Some(map) = maps.maps.get(&number) else {
+                        println!("client {client_id} asked for map {number} which doesn't exist");
+                        continue;
+                    };
 
-                    // send the maps to the client
-                    for (i, a) in &maps.maps {
-                        let message = ServerMessageUnreliable::Map {
-                            map: a.clone(),
-                            number: *i,
+                    // split the map up, send_map_chunks sends a few every update
+                    let client = clients.0.entry(client_id).or_default();
+                    for bytes in bincode::serialize(map).unwrap().chunks(MAP_CHUNK_SIZE) {
+                        let message = ServerMessageReliable::MapChunk {
+                            number,
+                            bytes: bytes.to_vec(),
                         };
+                        client
+                            .map_chunks
+                            .push_back(bincode::serialize(&message).unwrap());
+                    }
+                }
+
+                ClientMessageReliable::JoinLobby => {
+                    if let Some(client) = clients.0.get_mut(&client_id) {
+                        client.in_lobby = true;
+                    }
+                    broadcast_lobby(&mut server, &clients, &lobby, &maps);
+
+                    // the game has already started, so they don't have to wait
+                    if lobby.started {
                         server.send_message(
                             client_id,
-                            DefaultChannel::Unreliable,
+                            DefaultChannel::Reliable,
+                            bincode::serialize(&ServerMessageReliable::StartGame).unwrap(),
+                        );
+                        if race.start.is_some() {
+                            server.send_message(
+                                client_id,
+                                DefaultChannel::Reliable,
+                                bincode::serialize(&ServerMessageReliable::RaceStart).unwrap(),
+                            );
+                        }
+                    }
+                }
+
+                ClientMessageReliable::SetReady(ready) => {
+                    if let Some(client) = clients.0.get_mut(&client_id) {
+                        client.ready = ready;
+                    }
+                    broadcast_lobby(&mut server, &clients, &lobby, &maps);
+
+                    // a dedicated server has nobody to press start
+                    if lobby.host.is_none() && !lobby.started && lobby.everyone_ready(&clients) {
+                        start_game(&mut server, &mut lobby, &mut race, &clients);
+                    }
+                }
+
+                ClientMessageReliable::StartGame => {
+                    if lobby.host != Some(client_id) || lobby.started {
+                        continue;
+                    }
+                    if lobby.everyone_ready(&clients) {
+                        start_game(&mut server, &mut lobby, &mut race, &clients);
+                    } else {
+                        println!("the host tried to start before everyone was ready");
+                    }
+                }
+
+                ClientMessageReliable::SetGameMode(mode) => {
+                    // only the host can change the mode, and not after starting
+                    if lobby.host != Some(client_id) || lobby.started {
+                        continue;
+                    }
+                    lobby.mode = mode;
+                    broadcast_lobby(&mut server, &clients, &lobby, &maps);
+                }
+
+                ClientMessageReliable::ReachedGoal { level } => {
+                    // levels can't be skipped and the goal has to be nearby
+                    let now = time.elapsed_seconds_f64();
+                    let client = clients.0.entry(client_id).or_default();
+                    if let Err(reason) = client.movement.reach_goal(&maps, level, now) {
+                        clients.bad_move(client_id, &reason, now, &mut server);
+                        continue;
+                    }
+
+                    // only races are timed, and only once they have started
+                    let Some(start) = race.start else {
+                        continue;
+                    };
+                    let split = (time.elapsed_seconds_f64() - start) as f32;
+
+                    // people that joined late start with no splits
+                    let name = clients
+                        .0
+                        .get(&client_id)
+                        .map(|c| c.name.clone())
+                        .unwrap_or_default();
+                    let (_, splits) = race.results.entry(client_id).or_insert((name, Vec::new()));
+
+                    // levels have to be finished in order and only once
+                    if level as usize != splits.len() + 1 {
+                        continue;
+                    }
+                    splits.push(split);
+                    println!("client {client_id} finished level {level} at {split:.2}s");
+
+                    if splits.len() == maps.maps.len() {
+                        race.finish_order.push(client_id);
+                    }
+
+                    let message = ServerMessageReliable::Standings(race.standings());
+                    server.broadcast_message(
+                        DefaultChannel::Reliable,
+                        bincode::serialize(&message).unwrap(),
+                    );
+                }
+
+                ClientMessageReliable::GrabWall { level, wall_id } => {
+                    let wall = walls.0.entry((level, wall_id)).or_insert(SharedWall {
+                        pos: None,
+                        owner: None,
+                    });
+
+                    // the first client to ask gets the wall. anyone else
+                    // is told who has it so they let go
+                    if wall.owner.is_none() {
+                        wall.owner = Some(client_id);
+                        let message = ServerMessageReliable::WallOwner {
+                            level,
+                            wall_id,
+                            owner: wall.owner,
+                            pos: wall.pos,
+                        };
+                        server.broadcast_message(
+                            DefaultChannel::Reliable,
+                            bincode::serialize(&message).unwrap(),
+                        );
+                    } else if wall.owner != Some(client_id) {
+                        let message = ServerMessageReliable::WallOwner {
+                            level,
+                            wall_id,
+                            owner: wall.owner,
+                            pos: wall.pos,
+                        };
+                        server.send_message(
+                            client_id,
+                            DefaultChannel::Reliable,
+                            bincode::serialize(&message).unwrap(),
+                        );
+                    }
+                }
+
+                ClientMessageReliable::ReleaseWall {
+                    level,
+                    wall_id,
+                    pos,
+                } => {
+                    if let Some(wall) = walls.0.get_mut(&(level, wall_id)) {
+                        // you can only let go of a wall you are holding
+                        if wall.owner == Some(client_id) {
+                            wall.owner = None;
+                            wall.pos = Some(pos);
+                            let message = ServerMessageReliable::WallOwner {
+                                level,
+                                wall_id,
+                                owner: None,
+                                pos: Some(pos),
+                            };
+                            server.broadcast_message(
+                                DefaultChannel::Reliable,
+                                bincode::serialize(&message).unwrap(),
+                            );
+                        }
+                    }
+                }
+
+                ClientMessageReliable::RequestWalls => {
+                    // tell the client about every wall that has been moved or is being moved
+                    for ((level, wall_id), wall) in walls.0.iter() {
+                        let message = ServerMessageReliable::WallOwner {
+                            level: *level,
+                            wall_id: *wall_id,
+                            owner: wall.owner,
+                            pos: wall.pos,
+                        };
+                        server.send_message(
+                            client_id,
+                            DefaultChannel::Reliable,
                             bincode::serialize(&message).unwrap(),
                         );
                     }
@@ -164,18 +686,261 @@ fn server_update_system(mut server: ResMut<RenetServer>, maps: Res<Maps>) {
     while let Some(event) = server.get_event() {
         match event {
             // server tells us when a client has connected
-            ServerEvent::ClientConnected(client_id, _) => {
-                println!("Client {client_id} connected");
+            ServerEvent::ClientConnected(client_id, user_data) => {
+                let mut name = name_from_user_data(&user_data);
+                if name.is_empty() {
+                    name = format!("Player {}", client_id % 1000);
+                }
+                println!("Client {client_id} ({name}) connected");
+                let client = clients.0.entry(client_id).or_default();
+                client.name = name;
+
+                // it has reconnected, so it carries on from the level it was on.
+                // race results are kept by id so its standing is still there
+                if let Some((old, _)) = departed.0.remove(&client_id) {
+                    println!("Client {client_id} came back");
+                    client.in_lobby = old.in_lobby;
+                    client.ready = old.ready;
+                    client.movement = old.movement;
+                    client.malformed_packets = old.malformed_packets;
+                }
             }
             // server tells us when a client has disconnected
             ServerEvent::ClientDisconnected(client_id) => {
                 println!("Client {client_id} disconnected: BECAUSE");
-                let message = ServerMessageReliable::PlayerDisconnected { id: client_id };
-                server.broadcast_message(
-                    DefaultChannel::Reliable,
-                    bincode::serialize(&message).unwrap(),
-                )
+                let now = time.elapsed_seconds_f64();
+                departed
+                    .0
+                    .retain(|_, (_, left)| now - *left < SESSION_TIMEOUT);
+                let Some(client) = clients.0.remove(&client_id) else {
+                    continue;
+                };
+                broadcast_lobby(&mut server, &clients, &lobby, &maps);
+
+                // everyone has left, so the next people to join get a new lobby
+                if clients.0.is_empty() {
+                    lobby.started = false;
+                }
+                // nobody was told about clients that never said hello
+                if client.verified {
+                    let message = ServerMessageReliable::PlayerDisconnected { id: client_id };
+                    send_to_verified(&mut server, &clients, &message);
+                }
+                // kicked clients don't get to come back where they were
+                if client.verified && !client.kicked {
+                    departed.0.insert(client_id, (client, now));
+                }
+
+                // let go of any walls they were holding so other people can move them
+                for ((level, wall_id), wall) in walls.0.iter_mut() {
+                    if wall.owner == Some(client_id) {
+                        wall.owner = None;
+                        let message = ServerMessageReliable::WallOwner {
+                            level: *level,
+                            wall_id: *wall_id,
+                            owner: None,
+                            pos: wall.pos,
+                        };
+                        server.broadcast_message(
+                            DefaultChannel::Reliable,
+                            bincode::serialize(&message).unwrap(),
+                        );
+                    }
+                }
             }
         }
     }
 }
+
+// tells a client that just said hello who is here, and everyone else that it joined
+fn send_roster(server: &mut RenetServer, clients: &ServerClients, client_id: u64) {
+    let players = clients
+        .0
+        .iter()
+        .filter(|(_, client)| client.verified)
+        .map(|(id, client)| RosterPlayer {
+            id: *id,
+            name: client.name.clone(),
+        })
+        .collect();
+    server.send_message(
+        client_id,
+        DefaultChannel::Reliable,
+        bincode::serialize(&ServerMessageReliable::Roster(players)).unwrap(),
+    );
+
+    let message = ServerMessageReliable::PlayerConnected {
+        id: client_id,
+        name: clients.0[&client_id].name.clone(),
+    };
+    let message = bincode::serialize(&message).unwrap();
+    for (id, client) in clients.0.iter() {
+        if *id != client_id && client.verified {
+            server.send_message(*id, DefaultChannel::Reliable, message.clone());
+        }
+    }
+}
+
+// seconds since 1970, for the time on chat messages
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+// sends a message to every client that can read it
+fn send_to_verified(
+    server: &mut RenetServer,
+    clients: &ServerClients,
+    message: &ServerMessageReliable,
+) {
+    let message = bincode::serialize(message).unwrap();
+    for (id, client) in clients.0.iter() {
+        if client.verified {
+            server.send_message(*id, DefaultChannel::Reliable, message.clone());
+        }
+    }
+}
+
+// sends a message to every client in the lobby, or playing the game it started
+fn send_to_lobby(
+    server: &mut RenetServer,
+    clients: &ServerClients,
+    message: &ServerMessageReliable,
+) {
+    let message = bincode::serialize(message).unwrap();
+    for (id, client) in clients.0.iter() {
+        if client.verified && client.in_lobby {
+            server.send_message(*id, DefaultChannel::Reliable, message.clone());
+        }
+    }
+}
+
+// disconnects clients once they have had time to read why they were rejected
+fn kick_rejected_clients(
+    time: Res<Time>,
+    mut server: ResMut<RenetServer>,
+    mut clients: ResMut<ServerClients>,
+) {
+    for (client_id, client) in clients.0.iter_mut() {
+        let Some(timer) = &mut client.kick_timer else {
+            continue;
+        };
+        if timer.tick(time.delta()).just_finished() {
+            server.disconnect(*client_id);
+        }
+    }
+}
+
+// sends the next few chunks of any maps clients are downloading
+fn send_map_chunks(mut server: ResMut<RenetServer>, mut clients: ResMut<ServerClients>) {
+    for (client_id, client) in clients.0.iter_mut() {
+        for _ in 0..MAP_CHUNKS_PER_TICK {
+            let Some(message) = client.map_chunks.pop_front() else {
+                break;
+            };
+            server.send_message(*client_id, DefaultChannel::Reliable, message);
+        }
+    }
+}
+
+// tells everyone in the lobby who is there and what maps are loaded
+fn broadcast_lobby(
+    server: &mut RenetServer,
+    clients: &ServerClients,
+    lobby: &ServerLobby,
+    maps: &Maps,
+) {
+    let mut players: Vec<LobbyPlayer> = clients
+        .0
+        .iter()
+        .filter(|(_, client)| client.in_lobby)
+        .map(|(id, client)| LobbyPlayer {
+            id: *id,
+            name: client.name.clone(),
+            ready: client.ready,
+        })
+        .collect();
+    // keep the list in the same order for everyone
+    players.sort_by_key(|player| player.id);
+
+    let map_pack = maps.names();
+
+    let message = ServerMessageReliable::LobbyUpdate {
+        players,
+        host: lobby.host,
+        map_pack,
+        mode: lobby.mode,
+    };
+    send_to_lobby(server, clients, &message);
+}
+
+// moves everyone in the lobby to level 1 at the same time
+fn start_game(
+    server: &mut RenetServer,
+    lobby: &mut ServerLobby,
+    race: &mut ServerRace,
+    clients: &ServerClients,
+) {
+    println!("starting the game");
+    lobby.started = true;
+    send_to_lobby(server, clients, &ServerMessageReliable::StartGame);
+
+    if lobby.mode == GameMode::Race {
+        // everyone in the lobby is racing, they can't move until the countdown ends
+        *race = ServerRace {
+            countdown: Some(Timer::from_seconds(RACE_COUNTDOWN as f32, TimerMode::Once)),
+            last_count: RACE_COUNTDOWN,
+            results: clients
+                .0
+                .iter()
+                .filter(|(_, client)| client.in_lobby)
+                .map(|(id, client)| (*id, (client.name.clone(), Vec::new())))
+                .collect(),
+            ..default()
+        };
+        send_to_lobby(
+            server,
+            clients,
+            &ServerMessageReliable::Countdown(RACE_COUNTDOWN),
+        );
+        send_to_lobby(
+            server,
+            clients,
+            &ServerMessageReliable::Standings(race.standings()),
+        );
+    }
+}
+
+// counts down to the start of a race and tells everyone when to go
+fn race_countdown(
+    time: Res<Time>,
+    mut server: ResMut<RenetServer>,
+    clients: Res<ServerClients>,
+    mut race: ResMut<ServerRace>,
+) {
+    let Some(countdown) = &mut race.countdown else {
+        return;
+    };
+    countdown.tick(time.delta());
+
+    if countdown.finished() {
+        race.countdown = None;
+        race.start = Some(time.elapsed_seconds_f64());
+        println!("the race has started");
+        send_to_lobby(&mut server, &clients, &ServerMessageReliable::RaceStart);
+        return;
+    }
+
+    // tell everyone each time it goes down a second
+    let count = countdown.remaining_secs().ceil() as u8;
+    if count != race.last_count {
+        race.last_count = count;
+        send_to_lobby(
+            &mut server,
+            &clients,
+            &ServerMessageReliable::Countdown(count),
+        );
+    }
+}
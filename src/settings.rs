@@ -0,0 +1,191 @@
+use std::{
+    fs,
+    net::{IpAddr, Ipv4Addr},
+};
+
+use bevy::{prelude::*, window::PrimaryWindow, window::WindowMode};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{config_path, save_config},
+    hosting::HostMode,
+    interpolation::InterpolationSettings,
+    messages::clean_name,
+    server::{CLIENT_PORT, SERVER_PORT},
+};
+
+// the file in the config folder the settings are saved in
+const SETTINGS_FILE: &str = "settings.toml";
+
+// how many servers the join menu remembers
+const MAX_RECENT_SERVERS: usize = 5;
+
+pub struct SettingsPlugin;
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(load_settings())
+            // runs on the first frame too, so the saved window mode is used at startup
+            .add_system(apply_settings);
+    }
+}
+
+// everything the player can change from the settings menu. it is saved as
+// toml so it can be edited by hand too, anything missing uses the default
+#[derive(Resource, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    // the name other players see, it is sent to the server when connecting
+    pub player_name: String,
+    // the port a hosted server listens on and the port joined servers are expected on
+    pub server_port: u16,
+    // the first port clients try to listen on, the next ones are used if it is taken
+    pub client_port: u16,
+    // the address a hosted server listens on, 0.0.0.0 means every network
+    pub bind_address: IpAddr,
+    // who can join when we host
+    pub host_mode: HostMode,
+    // only players with a token from us can join when we host
+    pub secure: bool,
+    pub window_mode: WindowSetting,
+    // from 0 to 1
+    pub volume: f32,
+    // addresses joined before as they were typed, the newest first
+    pub recent_servers: Vec<String>,
+    // not in the menu, it is a table so it has to go last in the file
+    pub interpolation: InterpolationSettings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            player_name: default_name(),
+            server_port: SERVER_PORT,
+            client_port: CLIENT_PORT,
+            bind_address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            host_mode: HostMode::Lan,
+            secure: false,
+            window_mode: WindowSetting::Windowed,
+            volume: 1.0,
+            recent_servers: vec![],
+            interpolation: InterpolationSettings::default(),
+        }
+    }
+}
+
+// the window modes that can be picked in the settings menu
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WindowSetting {
+    Windowed,
+    Borderless,
+    Fullscreen,
+}
+
+impl WindowSetting {
+    // the next one along, used by the arrow keys in the settings menu
+    pub fn next(self) -> Self {
+        match self {
+            WindowSetting::Windowed => WindowSetting::Borderless,
+            WindowSetting::Borderless => WindowSetting::Fullscreen,
+            WindowSetting::Fullscreen => WindowSetting::Windowed,
+        }
+    }
+
+    pub fn previous(self) -> Self {
+        self.next().next()
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            WindowSetting::Windowed => "Windowed",
+            WindowSetting::Borderless => "Borderless fullscreen",
+            WindowSetting::Fullscreen => "Fullscreen",
+        }
+    }
+
+    fn window_mode(self) -> WindowMode {
+        match self {
+            WindowSetting::Windowed => WindowMode::Windowed,
+            WindowSetting::Borderless => WindowMode::BorderlessFullscreen,
+            WindowSetting::Fullscreen => WindowMode::Fullscreen,
+        }
+    }
+}
+
+impl Settings {
+    // moves the address to the top of the recent servers
+    pub fn remember_server(&mut self, address: &str) {
+        self.recent_servers.retain(|recent| recent != address);
+        self.recent_servers.insert(0, address.to_string());
+        self.recent_servers.truncate(MAX_RECENT_SERVERS);
+    }
+
+    // writes the settings to the config folder. it only prints if it fails,
+    // the settings are still used until the game is closed
+    pub fn save(&self) {
+        let result = toml::to_string_pretty(self)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| save_config(SETTINGS_FILE, &contents));
+        if let Err(e) = result {
+            println!("could not save the settings: {e}");
+        }
+    }
+}
+
+// reads the saved settings, or the defaults if there aren't any or they are broken
+pub fn load_settings() -> Settings {
+    let path = config_path(SETTINGS_FILE);
+    let Ok(contents) = fs::read_to_string(&path) else {
+        return Settings::default();
+    };
+
+    match toml::from_str::<Settings>(&contents) {
+        Ok(mut settings) => {
+            // the file could have been edited by hand
+            settings.player_name = clean_name(&settings.player_name);
+            if settings.player_name.is_empty() {
+                settings.player_name = default_name();
+            }
+            settings.volume = settings.volume.clamp(0.0, 1.0);
+            let interpolation = &mut settings.interpolation;
+            interpolation.delay = interpolation.delay.max(0.0);
+            interpolation.max_extrapolation = interpolation.max_extrapolation.max(0.0);
+            settings
+        }
+        Err(e) => {
+            println!(
+                "could not read {}: {e}, using the default settings",
+                path.display()
+            );
+            Settings::default()
+        }
+    }
+}
+
+// uses the name of the person logged in to the computer until they type one
+fn default_name() -> String {
+    let name = std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_default();
+    let name = clean_name(&name);
+    if name.is_empty() {
+        "Player".to_string()
+    } else {
+        name
+    }
+}
+
+// puts the window mode into effect whenever the settings change. the volume is
+// only stored for now, the game has no sounds to play it through yet
+fn apply_settings(settings: Res<Settings>, mut windows: Query<&mut Window, With<PrimaryWindow>>) {
+    if !settings.is_changed() {
+        return;
+    }
+
+    for mut window in windows.iter_mut() {
+        let mode = settings.window_mode.window_mode();
+        if window.mode != mode {
+            window.mode = mode;
+        }
+    }
+}
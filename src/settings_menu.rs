@@ -0,0 +1,279 @@
+use std::net::IpAddr;
+
+use bevy::prelude::*;
+
+use crate::{
+    hosting::HostMode,
+    messages::{clean_name, MAX_NAME_LENGTH},
+    settings::{Settings, WindowSetting},
+    startup_plugin::despawn_everything,
+    GameState, BACKGROUND_COLOUR,
+};
+
+// how much the arrow keys change the volume by
+const VOLUME_STEP: f32 = 0.1;
+
+pub struct SettingsMenuPlugin;
+
+impl Plugin for SettingsMenuPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(setup_settings_menu.in_schedule(OnEnter(GameState::Settings)))
+            .add_system(settings_input.in_set(OnUpdate(GameState::Settings)))
+            .add_system(settings_text_input.in_set(OnUpdate(GameState::Settings)))
+            .add_system(update_settings_text.in_set(OnUpdate(GameState::Settings)))
+            .add_system(despawn_everything.in_schedule(OnExit(GameState::Settings)));
+    }
+}
+
+// the rows of the settings menu, in order
+#[derive(Clone, Copy, PartialEq)]
+enum Field {
+    Name,
+    ServerPort,
+    ClientPort,
+    BindAddress,
+    HostMode,
+    Secure,
+    WindowMode,
+    Volume,
+}
+
+const FIELDS: [Field; 8] = [
+    Field::Name,
+    Field::ServerPort,
+    Field::ClientPort,
+    Field::BindAddress,
+    Field::HostMode,
+    Field::Secure,
+    Field::WindowMode,
+    Field::Volume,
+];
+
+// the settings being edited. the typed ones are kept as text
+// until they are saved so half typed values can be shown
+#[derive(Resource)]
+struct SettingsDraft {
+    selected: usize,
+    name: String,
+    server_port: String,
+    client_port: String,
+    bind_address: String,
+    host_mode: HostMode,
+    secure: bool,
+    window_mode: WindowSetting,
+    volume: f32,
+    // shown under the settings, eg why they couldn't be saved
+    message: String,
+}
+
+impl SettingsDraft {
+    fn new(settings: &Settings) -> Self {
+        SettingsDraft {
+            selected: 0,
+            name: settings.player_name.clone(),
+            server_port: settings.server_port.to_string(),
+            client_port: settings.client_port.to_string(),
+            bind_address: settings.bind_address.to_string(),
+            host_mode: settings.host_mode,
+            secure: settings.secure,
+            window_mode: settings.window_mode,
+            volume: settings.volume,
+            message: String::new(),
+        }
+    }
+
+    // the text of the selected row if it can be typed in
+    fn selected_text(&mut self) -> Option<&mut String> {
+        match FIELDS[self.selected] {
+            Field::Name => Some(&mut self.name),
+            Field::ServerPort => Some(&mut self.server_port),
+            Field::ClientPort => Some(&mut self.client_port),
+            Field::BindAddress => Some(&mut self.bind_address),
+            Field::HostMode | Field::Secure | Field::WindowMode | Field::Volume => None,
+        }
+    }
+
+    // checks everything typed and turns it into settings,
+    // anything that isn't in the menu is kept from the old ones
+    fn to_settings(&self, old: &Settings) -> Result<Settings, String> {
+        let player_name = clean_name(&self.name);
+        if player_name.is_empty() {
+            return Err("the name can't be empty".to_string());
+        }
+        let server_port = parse_port(&self.server_port, "server port")?;
+        let client_port = parse_port(&self.client_port, "client port")?;
+        let bind_address = self
+            .bind_address
+            .trim()
+            .parse::<IpAddr>()
+            .map_err(|_| format!("{} is not an ip address", self.bind_address))?;
+
+        Ok(Settings {
+            player_name,
+            server_port,
+            client_port,
+            bind_address,
+            host_mode: self.host_mode,
+            secure: self.secure,
+            window_mode: self.window_mode,
+            volume: self.volume,
+            ..old.clone()
+        })
+    }
+}
+
+fn parse_port(text: &str, name: &str) -> Result<u16, String> {
+    match text.trim().parse::<u16>() {
+        Ok(port) if port != 0 => Ok(port),
+        _ => Err(format!("the {name} has to be a number from 1 to 65535")),
+    }
+}
+
+#[derive(Component)]
+struct SettingsText;
+
+fn setup_settings_menu(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    settings: Res<Settings>,
+) {
+    commands.insert_resource(SettingsDraft::new(&settings));
+    commands.insert_resource(ClearColor(BACKGROUND_COLOUR));
+    commands.spawn(Camera2dBundle::default());
+
+    let style = TextStyle {
+        font: asset_server.load("fonts/Rubik-SemiBold.ttf"),
+        font_size: 40.0,
+        color: Color::BLACK,
+    };
+
+    // a title, a line for each setting and the help at the bottom
+    let mut sections = vec![TextSection::new("Settings\n\n", style.clone())];
+    sections.extend(FIELDS.iter().map(|_| TextSection::new("", style.clone())));
+    sections.push(TextSection::new("", style));
+
+    commands.spawn((TextBundle::from_sections(sections), SettingsText));
+}
+
+fn settings_input(
+    keys: Res<Input<KeyCode>>,
+    mut draft: ResMut<SettingsDraft>,
+    mut settings: ResMut<Settings>,
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    if keys.just_pressed(KeyCode::Up) {
+        draft.selected = (draft.selected + FIELDS.len() - 1) % FIELDS.len();
+    }
+    if keys.just_pressed(KeyCode::Down) {
+        draft.selected = (draft.selected + 1) % FIELDS.len();
+    }
+
+    // the arrow keys change the settings that aren't typed
+    let left = keys.just_pressed(KeyCode::Left);
+    let right = keys.just_pressed(KeyCode::Right);
+    match FIELDS[draft.selected] {
+        Field::HostMode if left => draft.host_mode = draft.host_mode.previous(),
+        Field::HostMode if right => draft.host_mode = draft.host_mode.next(),
+        Field::Secure if left || right => draft.secure = !draft.secure,
+        Field::WindowMode if left => draft.window_mode = draft.window_mode.previous(),
+        Field::WindowMode if right => draft.window_mode = draft.window_mode.next(),
+        Field::Volume if left => draft.volume = (draft.volume - VOLUME_STEP).max(0.0),
+        Field::Volume if right => draft.volume = (draft.volume + VOLUME_STEP).min(1.0),
+        _ => (),
+    }
+
+    // enter saves, the settings are used straight away
+    if keys.just_pressed(KeyCode::Return) {
+        match draft.to_settings(&settings) {
+            Ok(new_settings) => {
+                new_settings.save();
+                *settings = new_settings;
+                draft.message = "Saved".to_string();
+            }
+            Err(e) => draft.message = e,
+        }
+    }
+
+    // escape leaves without saving
+    if keys.just_pressed(KeyCode::Escape) {
+        game_state.set(GameState::Menu);
+    }
+}
+
+fn settings_text_input(
+    mut char_evr: EventReader<ReceivedCharacter>,
+    mut draft: ResMut<SettingsDraft>,
+) {
+    let selected = FIELDS[draft.selected];
+    let Some(text) = draft.selected_text() else {
+        // nothing to type in, but the events still have to be used up
+        char_evr.clear();
+        return;
+    };
+
+    for ev in char_evr.iter() {
+        let char = ev.char;
+
+        // backspace
+        if char == '\x08' {
+            text.pop();
+            continue;
+        }
+        if char.is_control() {
+            continue;
+        }
+
+        let allowed = match selected {
+            Field::Name => text.chars().count() < MAX_NAME_LENGTH,
+            // the biggest port is 5 digits
+            Field::ServerPort | Field::ClientPort => char.is_ascii_digit() && text.len() < 5,
+            // ipv4 or ipv6
+            Field::BindAddress => char.is_ascii_hexdigit() || char == '.' || char == ':',
+            Field::HostMode | Field::Secure | Field::WindowMode | Field::Volume => false,
+        };
+        if allowed {
+            text.push(char);
+        }
+    }
+}
+
+fn update_settings_text(draft: Res<SettingsDraft>, mut text: Query<&mut Text, With<SettingsText>>) {
+    for mut text in &mut text {
+        for (i, field) in FIELDS.iter().enumerate() {
+            let value = match field {
+                Field::Name => format!("Name: {}\n", draft.name),
+                Field::ServerPort => format!("Server port: {}\n", draft.server_port),
+                Field::ClientPort => format!("Client port: {}\n", draft.client_port),
+                Field::BindAddress => format!("Host on address: {}\n", draft.bind_address),
+                Field::HostMode => format!("Host for: < {} >\n", draft.host_mode.name()),
+                Field::Secure => format!(
+                    "Secure hosting: < {} >\n",
+                    if draft.secure { "On" } else { "Off" }
+                ),
+                Field::WindowMode => format!("Window: < {} >\n", draft.window_mode.name()),
+                Field::Volume => format!("Volume: < {:.0}% >\n", draft.volume * 100.0),
+            };
+
+            let section = &mut text.sections[i + 1];
+            if section.value != value {
+                section.value = value;
+            }
+
+            // the selected setting is white like the main menu
+            section.style.color = if i == draft.selected {
+                Color::WHITE
+            } else {
+                Color::BLACK
+            };
+        }
+
+        let help = format!(
+            "\n{}\nUp/Down: choose   Left/Right: change   Enter: save   Escape: back",
+            draft.message
+        );
+        let last = text.sections.len() - 1;
+        if text.sections[last].value != help {
+            text.sections[last].value = help;
+        }
+    }
+}
@@ -105,7 +105,9 @@ fn camera_follow_player(
 ) {
     // get the camera and player
     let (camera, mut vel) = camera.single_mut();
-    let player = player.single();
+    let Ok(player) = player.get_single() else {
+        return;
+    };
 
     // the vector going from the camera to the player multiplied by 2
     let velocity = (player.translation - camera.translation).truncate() * 2.0;
@@ -132,8 +134,8 @@ pub fn despawn_everything(
     }
 
     // all players are despawned so set their booleans to false
-    for player in player_map.0.iter_mut() {
-        player.1.2 = false
+    for player in player_map.0.values_mut() {
+        player.spawned = false
     }
 
     // despawn the camera
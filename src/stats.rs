@@ -0,0 +1,343 @@
+use std::{collections::HashMap, fs};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{config_path, save_ron},
+    editor::EditorPlaytest,
+    level::level_hash,
+    platform::Maps,
+    player::PlayerDied,
+    race::{format_time, Race},
+    run_if::run_if_not_waiting_for_race,
+    CurrentLevel, GameState,
+};
+
+// the best times, in the config folder
+const RECORDS_FILE: &str = "records.ron";
+// how many times the par time a level can take and still get a medal
+const SILVER_PAR: f32 = 1.5;
+const BRONZE_PAR: f32 = 2.0;
+// how wide each column of the win screen table is
+const LEVEL_COLUMN_WIDTH: f32 = 120.0;
+const STAT_COLUMN_WIDTH: f32 = 140.0;
+
+pub struct StatsPlugin;
+
+impl Plugin for StatsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(RunStats::default())
+            // a run starts from the menu or the lobby
+            .add_system(reset_stats.in_schedule(OnEnter(GameState::Menu)))
+            .add_system(reset_stats.in_schedule(OnEnter(GameState::Lobby)))
+            .add_system(setup_hud.in_schedule(OnEnter(GameState::Gameplay)))
+            .add_system(
+                tick_stats
+                    .in_set(OnUpdate(GameState::Gameplay))
+                    .run_if(run_if_not_waiting_for_race),
+            )
+            // not just in gameplay, the player dies on the frame it ends
+            .add_system(count_deaths)
+            .add_system(
+                update_hud
+                    .after(tick_stats)
+                    .in_set(OnUpdate(GameState::Gameplay)),
+            )
+            .add_system(finish_level.in_schedule(OnEnter(GameState::NextLevel)))
+            .add_system(show_records.in_schedule(OnEnter(GameState::Win)));
+    }
+}
+
+// a level that has been finished in this run
+pub struct LevelStats {
+    pub number: u8,
+    pub hash: u64,
+    pub par_time: Option<f32>,
+    pub time: f32,
+    pub deaths: u32,
+}
+
+// how the run is going. only time spent playing counts, not the death
+// and next level screens
+#[derive(Resource, Default)]
+pub struct RunStats {
+    // the level being played, all attempts at it added up
+    pub level_time: f32,
+    pub level_deaths: u32,
+    // the whole run
+    pub run_time: f32,
+    pub deaths: u32,
+    pub finished: Vec<LevelStats>,
+}
+
+// for finishing a level close to its par time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Medal {
+    Gold,
+    Silver,
+    Bronze,
+}
+
+impl Medal {
+    pub fn for_time(time: f32, par_time: f32) -> Option<Medal> {
+        if time <= par_time {
+            Some(Medal::Gold)
+        } else if time <= par_time * SILVER_PAR {
+            Some(Medal::Silver)
+        } else if time <= par_time * BRONZE_PAR {
+            Some(Medal::Bronze)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Medal::Gold => "Gold",
+            Medal::Silver => "Silver",
+            Medal::Bronze => "Bronze",
+        }
+    }
+}
+
+// the best times ever, by level hash so a changed level starts again
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Records {
+    levels: HashMap<u64, f32>,
+    // whole runs, by the hash of all of their levels
+    runs: HashMap<u64, f32>,
+}
+
+impl Records {
+    // writes the records to the config folder. it only prints if it fails
+    fn save(&self) {
+        if let Err(e) = save_ron(RECORDS_FILE, self) {
+            println!("could not save the best times: {e}");
+        }
+    }
+}
+
+// reads the saved records, or none if there aren't any or they are broken
+fn load_records() -> Records {
+    let path = config_path(RECORDS_FILE);
+    let Ok(contents) = fs::read_to_string(&path) else {
+        return Records::default();
+    };
+
+    match ron::from_str::<Records>(&contents) {
+        Ok(records) => records,
+        Err(e) => {
+            println!(
+                "could not read {}: {e}, starting the best times again",
+                path.display()
+            );
+            Records::default()
+        }
+    }
+}
+
+// the hashes of a run's levels combined, the same way a level is hashed
+fn run_hash(levels: &[LevelStats]) -> u64 {
+    levels.iter().fold(0xcbf29ce484222325, |hash, level| {
+        (hash ^ level.hash).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[derive(Component)]
+struct Hud;
+
+fn reset_stats(mut commands: Commands) {
+    commands.insert_resource(RunStats::default());
+}
+
+fn setup_hud(mut commands: Commands, asset_server: Res<AssetServer>) {
+    // the bottom right corner, out of the way of the chat, toasts and race clock
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font: asset_server.load("fonts/Rubik-SemiBold.ttf"),
+                font_size: 30.0,
+                color: Color::BLACK,
+            },
+        )
+        .with_text_alignment(TextAlignment::Right)
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                bottom: Val::Px(10.0),
+                right: Val::Px(10.0),
+                ..default()
+            },
+            ..default()
+        }),
+        Hud,
+    ));
+}
+
+fn tick_stats(time: Res<Time>, mut stats: ResMut<RunStats>) {
+    stats.level_time += time.delta_seconds();
+    stats.run_time += time.delta_seconds();
+}
+
+fn count_deaths(mut died: EventReader<PlayerDied>, mut stats: ResMut<RunStats>) {
+    // falling and hitting a killer wall on the same frame is one death
+    if died.iter().count() > 0 {
+        stats.level_deaths += 1;
+        stats.deaths += 1;
+    }
+}
+
+fn update_hud(
+    stats: Res<RunStats>,
+    current_level: Res<CurrentLevel>,
+    mut text: Query<&mut Text, With<Hud>>,
+) {
+    for mut text in &mut text {
+        text.sections[0].value = format!(
+            "Level {}   {}\nRun {}   Deaths {}",
+            current_level.level_number,
+            format_time(stats.level_time),
+            format_time(stats.run_time),
+            stats.deaths
+        );
+    }
+}
+
+// the goal has been reached. the level number has already gone up
+fn finish_level(mut stats: ResMut<RunStats>, current_level: Res<CurrentLevel>, maps: Res<Maps>) {
+    let number = current_level.level_number - 1;
+    let Some(level) = maps.maps.get(&number) else {
+        return;
+    };
+
+    let finished = LevelStats {
+        number,
+        hash: level_hash(level),
+        par_time: level.par_time,
+        time: stats.level_time,
+        deaths: stats.level_deaths,
+    };
+    stats.finished.push(finished);
+    stats.level_time = 0.0;
+    stats.level_deaths = 0;
+}
+
+// saves any new best times and shows them with the medals on the win screen
+fn show_records(
+    mut commands: Commands,
+    stats: Res<RunStats>,
+    race: Res<Race>,
+    playtest: Res<EditorPlaytest>,
+    asset_server: Res<AssetServer>,
+) {
+    // races have their own results table
+    if race.racing || playtest.0 || stats.finished.is_empty() {
+        return;
+    }
+
+    let mut records = load_records();
+    let mut rows = vec![vec![
+        "Level".to_string(),
+        "Time".to_string(),
+        "Best".to_string(),
+        "Par".to_string(),
+        "Medal".to_string(),
+        "Deaths".to_string(),
+    ]];
+
+    for level in &stats.finished {
+        let best = records.levels.get(&level.hash).copied();
+        let new_best = best.is_none_or(|best| level.time < best);
+        if new_best {
+            records.levels.insert(level.hash, level.time);
+        }
+
+        let medal = level
+            .par_time
+            .and_then(|par_time| Medal::for_time(level.time, par_time));
+        rows.push(vec![
+            level.number.to_string(),
+            format_time(level.time),
+            best_text(best, new_best),
+            level.par_time.map_or("-".to_string(), format_time),
+            medal.map_or("-", |medal| medal.name()).to_string(),
+            level.deaths.to_string(),
+        ]);
+    }
+
+    let hash = run_hash(&stats.finished);
+    let best = records.runs.get(&hash).copied();
+    let new_best = best.is_none_or(|best| stats.run_time < best);
+    if new_best {
+        records.runs.insert(hash, stats.run_time);
+    }
+    rows.push(vec![
+        "Total".to_string(),
+        format_time(stats.run_time),
+        best_text(best, new_best),
+        String::new(),
+        String::new(),
+        stats.deaths.to_string(),
+    ]);
+
+    records.save();
+
+    let style = TextStyle {
+        font: asset_server.load("fonts/Rubik-SemiBold.ttf"),
+        font_size: 30.0,
+        color: Color::BLACK,
+    };
+
+    // under the you win picture
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    bottom: Val::Px(20.0),
+                    left: Val::Px(20.0),
+                    ..default()
+                },
+                flex_direction: FlexDirection::Column,
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|table| {
+            for row in rows {
+                table
+                    .spawn(NodeBundle {
+                        style: Style {
+                            flex_direction: FlexDirection::Row,
+                            ..default()
+                        },
+                        ..default()
+                    })
+                    .with_children(|row_node| {
+                        for (column, cell) in row.into_iter().enumerate() {
+                            let width = match column {
+                                0 => LEVEL_COLUMN_WIDTH,
+                                _ => STAT_COLUMN_WIDTH,
+                            };
+                            row_node.spawn(
+                                TextBundle::from_section(cell, style.clone()).with_style(Style {
+                                    size: Size::new(Val::Px(width), Val::Auto),
+                                    ..default()
+                                }),
+                            );
+                        }
+                    });
+            }
+        });
+}
+
+// the best time before this run, or that this run beat it
+fn best_text(best: Option<f32>, new_best: bool) -> String {
+    match (best, new_best) {
+        (_, true) => "New best!".to_string(),
+        (Some(best), false) => format_time(best),
+        (None, false) => "-".to_string(),
+    }
+}
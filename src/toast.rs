@@ -0,0 +1,92 @@
+use std::{collections::VecDeque, time::Duration};
+
+use bevy::prelude::*;
+
+// how long a toast is on screen for
+const TOAST_TIME: Duration = Duration::from_secs(4);
+// it fades out over the last this many seconds
+const TOAST_FADE: f32 = 1.0;
+// older toasts are dropped when there are more than this
+const MAX_TOASTS: usize = 5;
+
+pub struct ToastPlugin;
+
+impl Plugin for ToastPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Toasts>().add_system(show_toasts);
+    }
+}
+
+// short messages shown in the corner of the screen in every state, newest last
+#[derive(Resource, Default)]
+pub struct Toasts(VecDeque<(String, Timer)>);
+
+impl Toasts {
+    pub fn push(&mut self, text: String) {
+        println!("{text}");
+        self.0
+            .push_back((text, Timer::new(TOAST_TIME, TimerMode::Once)));
+        if self.0.len() > MAX_TOASTS {
+            self.0.pop_front();
+        }
+    }
+}
+
+#[derive(Component)]
+struct ToastText;
+
+// the text is made again if a state change despawned it
+fn show_toasts(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut toasts: ResMut<Toasts>,
+    mut text: Query<(Entity, &mut Text), With<ToastText>>,
+    asset_server: Res<AssetServer>,
+) {
+    for (_, timer) in toasts.0.iter_mut() {
+        timer.tick(time.delta());
+    }
+    toasts.0.retain(|(_, timer)| !timer.finished());
+
+    if toasts.0.is_empty() {
+        for (entity, _) in text.iter() {
+            commands.entity(entity).despawn();
+        }
+        return;
+    }
+
+    let font = asset_server.load("fonts/Rubik-SemiBold.ttf");
+    let sections: Vec<TextSection> = toasts
+        .0
+        .iter()
+        .map(|(value, timer)| {
+            let alpha = (timer.remaining_secs() / TOAST_FADE).min(1.0);
+            TextSection::new(
+                format!("{value}\n"),
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 25.0,
+                    color: Color::rgba(0.0, 0.0, 0.0, alpha),
+                },
+            )
+        })
+        .collect();
+
+    if let Ok((_, mut text)) = text.get_single_mut() {
+        text.sections = sections;
+        return;
+    }
+
+    commands.spawn((
+        TextBundle::from_sections(sections).with_style(Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                top: Val::Px(10.0),
+                right: Val::Px(10.0),
+                ..default()
+            },
+            ..default()
+        }),
+        ToastText,
+    ));
+}
@@ -1,23 +1,40 @@
 use bevy::prelude::*;
 
 use crate::{
+    platform::Maps,
+    race::{format_time, Race},
     startup_plugin::{despawn_everything, GameTextures},
     GameState, BACKGROUND_COLOUR,
 };
 
+// how wide each column of the results table is
+const NAME_COLUMN_WIDTH: f32 = 200.0;
+const TIME_COLUMN_WIDTH: f32 = 120.0;
+
 pub struct WinPlugin;
 
 impl Plugin for WinPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
         app.add_system(setup_win_screen.in_schedule(OnEnter(GameState::Win)))
+            .add_system(update_results_table.in_set(OnUpdate(GameState::Win)))
             .add_system(despawn_everything.in_schedule(OnExit(GameState::Win)));
     }
 }
 
-fn setup_win_screen(mut commands: Commands, game_textures: Res<GameTextures>) {
+// the table of race results, it is rebuilt when new times come in
+#[derive(Component)]
+struct ResultsTable;
+
+fn setup_win_screen(mut commands: Commands, game_textures: Res<GameTextures>, race: Res<Race>) {
     // spawns the win screen, background, and camera
     commands.insert_resource(ClearColor(BACKGROUND_COLOUR));
     commands.spawn(Camera2dBundle::default());
+
+    // races show the results table instead
+    if race.racing {
+        return;
+    }
+
     commands.spawn(SpriteBundle {
         texture: game_textures.you_win.clone(),
         transform: Transform {
@@ -28,3 +45,97 @@ fn setup_win_screen(mut commands: Commands, game_textures: Res<GameTextures>) {
         ..Default::default()
     });
 }
+
+fn update_results_table(
+    mut commands: Commands,
+    race: Res<Race>,
+    maps: Res<Maps>,
+    asset_server: Res<AssetServer>,
+    table: Query<Entity, With<ResultsTable>>,
+) {
+    // only rebuild when the standings change (or the first time)
+    if !race.racing || (!race.is_changed() && !table.is_empty()) {
+        return;
+    }
+    for entity in table.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    let style = TextStyle {
+        font: asset_server.load("fonts/Rubik-SemiBold.ttf"),
+        font_size: 30.0,
+        color: Color::BLACK,
+    };
+
+    // one row for the headings and one for each player
+    let mut rows = vec![];
+    let mut heading = vec!["#".to_string(), "Name".to_string()];
+    heading.extend((1..=maps.maps.len()).map(|level| format!("Level {level}")));
+    heading.push("Total".to_string());
+    rows.push(heading);
+
+    for (i, standing) in race.standings.iter().enumerate() {
+        let mut row = vec![format!("{}", i + 1), standing.name.clone()];
+
+        // the splits are times since the start, show how long each level took
+        let mut last = 0.0;
+        for level in 0..maps.maps.len() {
+            match standing.splits.get(level) {
+                Some(split) => {
+                    row.push(format_time(split - last));
+                    last = *split;
+                }
+                None => row.push("-".to_string()),
+            }
+        }
+
+        // no total until they have finished
+        row.push(if standing.finished {
+            format_time(last)
+        } else {
+            "-".to_string()
+        });
+        rows.push(row);
+    }
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    flex_direction: FlexDirection::Column,
+                    margin: UiRect::all(Val::Px(20.0)),
+                    ..default()
+                },
+                ..default()
+            },
+            ResultsTable,
+        ))
+        .with_children(|table| {
+            for row in rows {
+                table
+                    .spawn(NodeBundle {
+                        style: Style {
+                            flex_direction: FlexDirection::Row,
+                            ..default()
+                        },
+                        ..default()
+                    })
+                    .with_children(|row_node| {
+                        for (column, cell) in row.into_iter().enumerate() {
+                            // the place is narrow, the name is wide
+                            let width = match column {
+                                0 => 50.0,
+                                1 => NAME_COLUMN_WIDTH,
+                                _ => TIME_COLUMN_WIDTH,
+                            };
+                            row_node.spawn(
+                                TextBundle::from_section(cell, style.clone()).with_style(Style {
+                                    size: Size::new(Val::Px(width), Val::Auto),
+                                    ..default()
+                                }),
+                            );
+                        }
+                    });
+            }
+        });
+}